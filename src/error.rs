@@ -1,3 +1,5 @@
+use crate::tool::stitcher::Position;
+
 #[derive(Debug)]
 pub struct UnknownError {
     pub name: String,
@@ -43,6 +45,124 @@ impl std::fmt::Display for MissingFieldError {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum StitchError {
+    /// Stitching needs at least two images, holds the number of images that were given.
+    TooFewImages(usize),
+    /// The match window has no rows, so every offset would match equally well.
+    EmptyWindow,
+    /// The match window does not fit inside the image once the crop is removed.
+    WindowLargerThanImage {
+        index: usize,
+        window_size: usize,
+        size: u32,
+    },
+    /// The crop removes the whole image along the stitch direction.
+    CropLargerThanImage { index: usize, crop: u32, size: u32 },
+    /// The image has no pixels across the stitch direction, so it cannot be scaled to match the
+    /// others.
+    EmptyImage { index: usize },
+    /// The found position places the second image completely outside of the first one.
+    OverlapOutOfBounds {
+        position: Position,
+        width: u32,
+        height: u32,
+    },
+    /// The match of a seam fell below the minimum confidence.
+    LowConfidence { seam: usize, confidence: f64 },
+    /// Exclusions were given per image, but not for as many images as are stitched.
+    ExclusionCountMismatch { images: usize, exclusions: usize },
+    /// The exclusions of the second image of a pair leave no run of rows as long as the window
+    /// to match it on.
+    NothingToMatch { first: usize, second: usize },
+    /// The offset hint of a pair leaves no offset where the images still overlap.
+    HintOutOfRange { first: usize, second: usize },
+    /// Compositing was asked to draw nothing.
+    NoPlacements,
+    /// A placement refers to an image that was not given.
+    PlacementOutOfRange { index: usize, images: usize },
+    /// The images do not fill every row of a grid with the given number of columns.
+    GridMismatch { images: usize, columns: usize },
+    /// The [`crate::tool::stitcher::CancelToken`] of the stitch was cancelled.
+    Cancelled,
+    /// The thread to stitch on could not be started, holds why.
+    ThreadPool(String),
+}
+
+impl std::fmt::Display for StitchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StitchError::TooFewImages(count) => {
+                write!(f, "Need at least two images to stitch, got {}", count)
+            }
+            StitchError::EmptyWindow => write!(f, "Window size has to be at least 1"),
+            StitchError::WindowLargerThanImage {
+                index,
+                window_size,
+                size,
+            } => write!(
+                f,
+                "Window size {} does not fit in image {} which only has {} usable rows",
+                window_size, index, size
+            ),
+            StitchError::CropLargerThanImage { index, crop, size } => write!(
+                f,
+                "Crop {} is larger than image {} which is only {} pixels long",
+                crop, index, size
+            ),
+            StitchError::EmptyImage { index } => {
+                write!(f, "Image {} is empty so it cannot be scaled", index)
+            }
+            StitchError::OverlapOutOfBounds {
+                position,
+                width,
+                height,
+            } => write!(
+                f,
+                "Overlap at ({}, {}) is outside of the {}x{} stitch bounds",
+                position.x, position.y, width, height
+            ),
+            StitchError::LowConfidence { seam, confidence } => write!(
+                f,
+                "Seam {} only matched with a confidence of {:.3}",
+                seam, confidence
+            ),
+            StitchError::ExclusionCountMismatch { images, exclusions } => write!(
+                f,
+                "Got exclusions for {} images while stitching {} images",
+                exclusions, images
+            ),
+            StitchError::NothingToMatch { first, second } => write!(
+                f,
+                "Image {} has no rows left to match onto image {} once its exclusions are left out",
+                second, first
+            ),
+            StitchError::HintOutOfRange { first, second } => write!(
+                f,
+                "The offset hint for images {} and {} leaves no offset where they overlap",
+                first, second
+            ),
+            StitchError::NoPlacements => write!(f, "Need at least one placement to composite"),
+            StitchError::PlacementOutOfRange { index, images } => write!(
+                f,
+                "Placement refers to image {} but only {} images were given",
+                index, images
+            ),
+            StitchError::GridMismatch { images, columns } => write!(
+                f,
+                "Cannot fill rows of {} columns with {} images",
+                columns, images
+            ),
+            StitchError::Cancelled => write!(f, "Stitching was cancelled"),
+            StitchError::ThreadPool(reason) => {
+                write!(f, "Could not start the thread to stitch on: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StitchError {}
+
 macro_rules! unknown_error_expected {
     ($($head:literal $(| $tail:literal)* => $type:literal),*) => {
         vec![
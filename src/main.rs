@@ -3,14 +3,21 @@ mod cli {
     use std::borrow::Cow;
     use std::cmp::Ordering;
     use std::fs::read_dir;
+    use std::io::{IsTerminal, Write, stderr};
     use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::time::Duration;
 
     use chrono::{Local, Timelike};
     use clap::Parser;
     use itertools::Itertools;
     use rayon::iter::IntoParallelRefIterator;
     use rayon::iter::ParallelIterator;
-    use wonfy_tools::tool::stitcher::{CheckDirection, ImageStitcherBuilder, MatchMode, Order};
+    use wonfy_tools::tool::stitcher::{
+        Align, BlendMode, CheckDirection, Color, Exclusions, ImageStitcherBuilder, LowConfidence,
+        MatchMode, Observer, OffsetHints, Order, ScaleMode, SeamMode, SearchMode, StickyMode,
+        StitchEvent, StitchObserver, Threads,
+    };
     use wonfy_tools::util::string::parsing::parse_first_number;
 
     #[derive(Parser, Debug)]
@@ -19,10 +26,10 @@ mod cli {
         /// List of files to stitch. Can be a directory or multiple files. Has to contain at least two files.
         #[arg(short, long, required = true, num_args = 1..)]
         files_to_stitch: Vec<String>,
-        /// Direction of the stitch operation.
+        /// Direction of the stitch operation, Auto picks it for every pair.
         #[arg(short, long, value_enum)]
         direction: CheckDirection,
-        /// Are the files ordered or not, defaults to Ordered.
+        /// Are the files ordered, unordered or the tiles of a grid row by row, defaults to Ordered.
         #[arg(short, long, value_enum)]
         order: Option<Order>,
         /// Output file path. If not provided, a default name will be generated.
@@ -37,6 +44,113 @@ mod cli {
         /// Number of pixels to crop the images while matching, defaults to 0.
         #[arg(short, long)]
         crop_padding: Option<u32>,
+        /// Search mode, defaults to Pyramid.
+        #[arg(short, long, value_enum)]
+        search_mode: Option<SearchMode>,
+        /// How overlapping images are blended at the seam, defaults to HardCut.
+        #[arg(short, long, value_enum)]
+        blend: Option<BlendMode>,
+        /// Number of pixels the blend transitions over, defaults to 32.
+        #[arg(long)]
+        blend_width: Option<u32>,
+        /// Where the seam between overlapping images is cut, defaults to Straight.
+        #[arg(long, value_enum)]
+        seam: Option<SeamMode>,
+        /// Where images of different sizes go across the stitch direction, defaults to Content.
+        #[arg(long, value_enum)]
+        align: Option<Align>,
+        /// Colour transparent parts of the stitched image are filled with as `#rrggbb` or `#rrggbbaa`, left transparent by default.
+        #[arg(long)]
+        background: Option<Color>,
+        /// Space added around the stitched image, defaults to 0.
+        #[arg(long)]
+        padding: Option<u32>,
+        /// Space left between neighbouring images instead of overlapping them, defaults to 0.
+        #[arg(long)]
+        gap: Option<u32>,
+        /// Colour of the gaps as `#rrggbb` or `#rrggbbaa`, defaults to the background.
+        #[arg(long)]
+        separator: Option<Color>,
+        /// Minimum confidence every seam has to match with, not checked by default.
+        #[arg(long)]
+        min_confidence: Option<f64>,
+        /// What to do with a seam below the minimum confidence, defaults to Fail.
+        #[arg(long, value_enum)]
+        low_confidence: Option<LowConfidence>,
+        /// Pixels with a lower alpha are left out when matching, defaults to 1.
+        #[arg(long)]
+        alpha_threshold: Option<u8>,
+        /// Areas left out when matching, as `x,y,width,height` separated by `;`. Separate groups with `|` to give each image its own.
+        #[arg(long)]
+        exclusions: Option<Exclusions>,
+        /// Whether sticky headers and footers are found and kept only once, defaults to Off.
+        #[arg(long, value_enum)]
+        sticky: Option<StickyMode>,
+        /// Where each image is expected from the previous one as `x,y~tolerance`, or how many lines it overlaps as `min..max`, separated by `;`. Leave one empty to search the whole pair.
+        #[arg(long)]
+        offset_hints: Option<OffsetHints>,
+        /// Number of tiles in every row when the order is Grid, picked from how well the tiles match when left out.
+        #[arg(long)]
+        grid_columns: Option<usize>,
+        /// How images captured at different zoom levels are brought to the same scale, defaults to Off.
+        #[arg(long, value_enum)]
+        scale: Option<ScaleMode>,
+        /// Seconds matching may take before the best offsets found so far are used, not limited by default.
+        #[arg(long)]
+        time_budget: Option<f64>,
+        /// Number of threads to match on, 1 runs single-threaded. Uses every core by default.
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Break ties between equally good offsets by picking the one overlapping the least, so every run gives the same result.
+        #[arg(long)]
+        deterministic: bool,
+    }
+
+    const PROGRESS_BAR_WIDTH: usize = 30;
+
+    /// Draws the search of the pair last heard from on a single line of stderr.
+    #[derive(Default)]
+    struct ProgressBar {
+        matched: AtomicUsize,
+    }
+
+    impl StitchObserver for ProgressBar {
+        fn on_event(&self, event: StitchEvent) {
+            let line = match event {
+                StitchEvent::PairStarted { .. } => return,
+                StitchEvent::PairProgress {
+                    first,
+                    second,
+                    direction,
+                    progress,
+                } => {
+                    let filled = (progress * PROGRESS_BAR_WIDTH as f64) as usize;
+
+                    format!(
+                        "[{}{}] {:>3}% image {} to {}, {:?} ({} pairs matched)",
+                        "#".repeat(filled),
+                        " ".repeat(PROGRESS_BAR_WIDTH - filled),
+                        (progress * 100.0) as u32,
+                        first,
+                        second,
+                        direction,
+                        self.matched.load(AtomicOrdering::Relaxed)
+                    )
+                }
+                StitchEvent::PairFinished { .. } => {
+                    self.matched.fetch_add(1, AtomicOrdering::Relaxed);
+                    return;
+                }
+                StitchEvent::CompositingStarted => "Compositing...".to_string(),
+                StitchEvent::CompositingFinished => {
+                    eprintln!("\r\x1b[2KCompositing done");
+                    return;
+                }
+            };
+
+            eprint!("\r\x1b[2K{}", line);
+            let _ = stderr().flush();
+        }
     }
 
     pub fn tool_main() {
@@ -135,6 +249,13 @@ mod cli {
 
         let window_size = args.window_size.unwrap_or(6);
         let match_mode = args.match_mode.unwrap_or(MatchMode::Edges);
+        let search_mode = args.search_mode.unwrap_or(SearchMode::Pyramid);
+        let blend = args.blend.unwrap_or(BlendMode::HardCut);
+        let blend = match args.blend_width {
+            Some(width) => blend.with_width(width),
+            None => blend,
+        };
+        let seam = args.seam.unwrap_or(SeamMode::Straight);
 
         let time = Local::now();
 
@@ -156,6 +277,10 @@ mod cli {
         println!("Order: {:#?}", order);
         println!("Window Size: {:#?}", window_size);
         println!("Match Mode: {:#?}", match_mode);
+        println!("Search Mode: {:#?}", search_mode);
+        println!("Blend: {:#?}", blend);
+        println!("Seam: {:#?}", seam);
+        println!("Minimum Confidence: {:#?}", args.min_confidence);
         println!("Output Path: {}", output_file_path.display());
         print!("Stitching Files in the following order: ");
 
@@ -170,6 +295,30 @@ mod cli {
             print!("{}{}", file.file_name().unwrap().to_string_lossy(), end);
         }
 
+        let threads = match args.threads {
+            None => Threads::Global,
+            Some(1) => Threads::Single,
+            Some(threads) => match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+                Ok(pool) => Threads::Pool(pool.into()),
+                Err(err) => {
+                    eprintln!("Failed to start {} threads: {}", threads, err);
+                    return;
+                }
+            },
+        };
+
+        let time_budget = match args
+            .time_budget
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+        {
+            Ok(time_budget) => time_budget,
+            Err(err) => {
+                eprintln!("Invalid time budget: {}", err);
+                return;
+            }
+        };
+
         let stitcher = ImageStitcherBuilder::new()
             .images(images)
             .direction(args.direction)
@@ -177,15 +326,89 @@ mod cli {
             .window_size(window_size)
             .match_mode(match_mode)
             .crop(args.crop_padding)
+            .search_mode(search_mode)
+            .blend(blend)
+            .seam(seam)
+            .align(args.align)
+            .background(args.background)
+            .padding(args.padding)
+            .gap(args.gap)
+            .separator(args.separator)
+            .min_confidence(args.min_confidence)
+            .low_confidence(args.low_confidence)
+            .alpha_threshold(args.alpha_threshold)
+            .exclusions(args.exclusions)
+            .sticky(args.sticky)
+            .offset_hints(args.offset_hints)
+            .grid_columns(args.grid_columns)
+            .scale(args.scale)
+            .time_budget(time_budget)
+            .threads(threads)
+            .deterministic(args.deterministic)
+            .observer(
+                stderr()
+                    .is_terminal()
+                    .then(|| Observer::new(ProgressBar::default())),
+            )
             .build()
             .unwrap();
 
-        let (final_image, stitch_regions) = stitcher.stitch();
+        let (final_image, report) = match stitcher.stitch() {
+            Ok(result) => result,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return;
+            }
+        };
 
-        for region in stitch_regions {
+        for region in report.positions {
             println!("Stitch region: {:#?}", region);
         }
 
+        for (index, input) in report.inputs.iter().enumerate() {
+            println!(
+                "Image {}: stitched as number {} at {}, {} ({}x{}, scale {:.3})",
+                index, input.sequence, input.x, input.y, input.width, input.height, input.scale
+            );
+        }
+
+        for (index, seam) in report.seams.iter().enumerate() {
+            println!(
+                "Seam {}: image {} to {}, {:?}, score {}, confidence {:.3}, mean difference {:.2}, ambiguity {:.3}{}{}{}",
+                index,
+                seam.first,
+                seam.second,
+                seam.confidence.direction,
+                seam.score,
+                seam.confidence.confidence,
+                seam.confidence.mean_difference,
+                seam.confidence.ambiguity,
+                if seam.flipped { " (flipped)" } else { "" },
+                if seam.confidence.flagged {
+                    " (low confidence)"
+                } else {
+                    ""
+                },
+                if seam.confidence.truncated {
+                    " (out of time)"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        if report.sticky_bands.header > 0 || report.sticky_bands.footer > 0 {
+            println!(
+                "Sticky bands removed: header {}, footer {}",
+                report.sticky_bands.header, report.sticky_bands.footer
+            );
+        }
+
+        println!(
+            "Direction {:?}, crop {}, matching took {:?}, compositing took {:?}",
+            report.direction, report.crop, report.timings.matching, report.timings.compositing
+        );
+
         final_image.save(output_file_path).unwrap();
     }
 }
@@ -0,0 +1,467 @@
+use image::{
+    ImageBuffer, Luma, Rgba, Rgba32FImage, RgbaImage,
+    imageops::{FilterType, crop_imm, resize},
+};
+
+use itertools::Itertools;
+
+use super::params::{BlendMode, CompositeOptions, Rect, SeamMode};
+
+type WeightMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// Re-blends the overlap of two images that were already composited with a hard cut.
+///
+/// `top_rect` and `bottom_rect` are where each image was placed in `output`, only pixels inside
+/// both of them are touched.
+pub(crate) fn blend_overlap(
+    output: &mut RgbaImage,
+    top_image: &RgbaImage,
+    top_rect: Rect,
+    bottom_image: &RgbaImage,
+    bottom_rect: Rect,
+    composite: &CompositeOptions,
+) {
+    let Some(overlap) = overlap_to_blend(output, top_rect, bottom_rect, composite) else {
+        return;
+    };
+
+    let top_part = crop_imm(
+        top_image,
+        overlap.x - top_rect.x,
+        overlap.y - top_rect.y,
+        overlap.width,
+        overlap.height,
+    )
+    .to_image();
+
+    blend_overlap_part(
+        output,
+        &top_part,
+        top_rect,
+        bottom_image,
+        bottom_rect,
+        overlap,
+        composite,
+    );
+}
+
+/// The area [`blend_overlap`] touches, `None` when the composite options keep the hard cut.
+pub(crate) fn overlap_to_blend(
+    output: &RgbaImage,
+    top_rect: Rect,
+    bottom_rect: Rect,
+    composite: &CompositeOptions,
+) -> Option<Rect> {
+    if blend_width(composite) == 0 && composite.seam == SeamMode::Straight {
+        return None;
+    }
+
+    let bounds = Rect::new(0, 0, output.width(), output.height());
+
+    top_rect
+        .intersection(&bottom_rect)
+        .and_then(|overlap| overlap.intersection(&bounds))
+}
+
+/// Same as [`blend_overlap`], with the top image already cut down to `overlap` for when it only
+/// exists in `output` and gets drawn over by the bottom image.
+pub(crate) fn blend_overlap_part(
+    output: &mut RgbaImage,
+    top_part: &RgbaImage,
+    top_rect: Rect,
+    bottom_image: &RgbaImage,
+    bottom_rect: Rect,
+    overlap: Rect,
+    composite: &CompositeOptions,
+) {
+    let width = blend_width(composite);
+
+    let bottom_part = crop_imm(
+        bottom_image,
+        overlap.x - bottom_rect.x,
+        overlap.y - bottom_rect.y,
+        overlap.width,
+        overlap.height,
+    )
+    .to_image();
+
+    let weights = match composite.seam {
+        SeamMode::Straight => seam_weights(top_rect, bottom_rect, overlap, width, true, true),
+        SeamMode::MinCost => min_cost_weights(
+            top_part,
+            &bottom_part,
+            top_rect,
+            bottom_rect,
+            overlap,
+            width,
+        ),
+    };
+
+    let blended = match composite.blend {
+        BlendMode::HardCut | BlendMode::Feather(_) => feather(top_part, &bottom_part, &weights),
+        BlendMode::MultiBand(_) => multi_band(top_part, &bottom_part, &weights, width),
+    };
+
+    for (x, y, pixel) in blended.enumerate_pixels() {
+        output.put_pixel(overlap.x + x, overlap.y + y, *pixel);
+    }
+}
+
+fn blend_width(composite: &CompositeOptions) -> u32 {
+    match composite.blend {
+        BlendMode::HardCut => 0,
+        BlendMode::Feather(width) | BlendMode::MultiBand(width) => width.max(1),
+    }
+}
+
+/// Weight of the bottom image for every pixel of the overlap.
+///
+/// The weight ramps up from the edges of the bottom image that lie inside the top image, which
+/// is where a hard cut would leave a visible line. The ramp is shortened when the top image ends
+/// before it finishes so the blend never jumps at the far side of the overlap. `vertical_edges`
+/// and `horizontal_edges` pick which of the edges are taken into account.
+fn seam_weights(
+    top: Rect,
+    bottom: Rect,
+    overlap: Rect,
+    width: u32,
+    vertical_edges: bool,
+    horizontal_edges: bool,
+) -> WeightMap {
+    WeightMap::from_fn(overlap.width, overlap.height, |x, y| {
+        let (x, y) = (overlap.x + x, overlap.y + y);
+
+        let seam_distance = [
+            (vertical_edges && bottom.x > top.x).then(|| x - bottom.x),
+            (vertical_edges && bottom.right() < top.right()).then(|| bottom.right() - 1 - x),
+            (horizontal_edges && bottom.y > top.y).then(|| y - bottom.y),
+            (horizontal_edges && bottom.bottom() < top.bottom()).then(|| bottom.bottom() - 1 - y),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        let far_distance = [
+            (vertical_edges && top.x > bottom.x).then(|| x - top.x),
+            (vertical_edges && top.right() < bottom.right()).then(|| top.right() - 1 - x),
+            (horizontal_edges && top.y > bottom.y).then(|| y - top.y),
+            (horizontal_edges && top.bottom() < bottom.bottom()).then(|| top.bottom() - 1 - y),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(u32::MAX);
+
+        let weight = match seam_distance {
+            Some(seam_distance) => {
+                let ramp = width.min(seam_distance.saturating_add(far_distance).saturating_add(1));
+                ((seam_distance + 1) as f32 / (ramp + 1) as f32).min(1.0)
+            }
+            None => 1.0,
+        };
+
+        Luma([weight])
+    })
+}
+
+/// Weight of the bottom image for every pixel of the overlap, switching images along the
+/// cheapest path through the per-pixel difference of the two and fading over `width` pixels
+/// around it.
+///
+/// The path runs across the overlap parallel to the edge the bottom image enters the top one
+/// from, edges running the other way keep the straight ramp from [`seam_weights`].
+fn min_cost_weights(
+    top: &RgbaImage,
+    bottom: &RgbaImage,
+    top_rect: Rect,
+    bottom_rect: Rect,
+    overlap: Rect,
+    width: u32,
+) -> WeightMap {
+    use std::cmp::Ordering;
+
+    let (along_x, bottom_after) = match (
+        bottom_rect.y.cmp(&top_rect.y),
+        bottom_rect.x.cmp(&top_rect.x),
+    ) {
+        (Ordering::Equal, Ordering::Equal) => {
+            return WeightMap::from_pixel(overlap.width, overlap.height, Luma([1.0]));
+        }
+        (Ordering::Equal, x) => (false, x == Ordering::Greater),
+        (y, _) => (true, y == Ordering::Greater),
+    };
+
+    // Maps between (along, across) and (x, y), swapping the axes is its own inverse.
+    let to_xy = |along: u32, across: u32| match along_x {
+        true => (along, across),
+        false => (across, along),
+    };
+
+    let (length, depth) = to_xy(overlap.width, overlap.height);
+
+    let differences = (0..length)
+        .flat_map(|along| {
+            (0..depth).map(move |across| {
+                let (x, y) = to_xy(along, across);
+                let (top, bottom) = (top.get_pixel(x, y), bottom.get_pixel(x, y));
+
+                match top[3] == 0 || bottom[3] == 0 {
+                    true => 0,
+                    false => (0..3).map(|c| top[c].abs_diff(bottom[c]) as u64).sum(),
+                }
+            })
+        })
+        .collect::<Vec<u64>>();
+
+    // The fade covers `width / 2` pixels on each side of the path, so a step costs everything
+    // it fades over rather than just the pixel it lands on.
+    let radius = width / 2;
+    let fade_costs = differences
+        .chunks_exact(depth as usize)
+        .flat_map(|column| {
+            let prefix = std::iter::once(0)
+                .chain(column.iter().scan(0, |sum, difference| {
+                    *sum += difference;
+                    Some(*sum)
+                }))
+                .collect::<Vec<_>>();
+
+            (0..depth).map(move |across| {
+                let start = across.saturating_sub(radius) as usize;
+                let end = (across + radius + 1).min(depth) as usize;
+                prefix[end] - prefix[start]
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Keep the path far enough from the sides of the overlap for the fade to fit on both sides.
+    let margin = radius.min(depth.saturating_sub(1) / 2);
+
+    let path = cheapest_path(length, depth, margin, &fade_costs);
+
+    let cross = seam_weights(top_rect, bottom_rect, overlap, width, along_x, !along_x);
+
+    WeightMap::from_fn(overlap.width, overlap.height, |x, y| {
+        let (along, across) = to_xy(x, y);
+        let distance = across as f32 - path[along as usize] as f32;
+        let distance = match bottom_after {
+            true => distance,
+            false => -distance,
+        };
+
+        let weight = match width {
+            0 => (distance >= 0.0) as u8 as f32,
+            width => ((distance + 0.5) / width as f32 + 0.5).clamp(0.0, 1.0),
+        };
+
+        Luma([weight * cross.get_pixel(x, y)[0]])
+    })
+}
+
+/// Dynamic programming search for the path with the lowest total cost that crosses `length`
+/// steps, moving at most one unit of `depth` per step and staying `margin` away from its sides.
+/// `costs` holds `depth` values per step. Returns the depth picked at every step.
+fn cheapest_path(length: u32, depth: u32, margin: u32, costs: &[u64]) -> Vec<u32> {
+    let first = margin;
+    let span = (depth - 2 * margin) as usize;
+    let length = length as usize;
+
+    let mut totals = vec![0u64; span * length];
+    let mut steps = vec![0i8; span * length];
+
+    for along in 0..length {
+        for index in 0..span {
+            let own = costs[along * depth as usize + first as usize + index];
+
+            if along == 0 {
+                totals[index] = own;
+                continue;
+            }
+
+            let previous = &totals[(along - 1) * span..along * span];
+            let (step, best) = [0i8, -1, 1]
+                .into_iter()
+                .filter_map(|step| {
+                    let neighbour = index as isize + step as isize;
+                    (0..span as isize)
+                        .contains(&neighbour)
+                        .then(|| (step, previous[neighbour as usize]))
+                })
+                .min_by_key(|(_, total)| *total)
+                .expect("staying on the same row is always possible");
+
+            totals[along * span + index] = own + best;
+            steps[along * span + index] = step;
+        }
+    }
+
+    let mut index = totals[(length - 1) * span..]
+        .iter()
+        .position_min()
+        .expect("path has at least one row");
+
+    let mut path = vec![0; length];
+
+    for along in (0..length).rev() {
+        path[along] = first + index as u32;
+        index = (index as isize + steps[along * span + index] as isize) as usize;
+    }
+
+    path
+}
+
+#[inline(always)]
+fn mix(top: &Rgba<u8>, bottom: &Rgba<u8>, weight: f32) -> Rgba<u8> {
+    if top[3] == 0 {
+        return *bottom;
+    }
+
+    if bottom[3] == 0 {
+        return *top;
+    }
+
+    Rgba(std::array::from_fn(|channel| {
+        (top[channel] as f32 * (1.0 - weight) + bottom[channel] as f32 * weight).round() as u8
+    }))
+}
+
+fn feather(top: &RgbaImage, bottom: &RgbaImage, weights: &WeightMap) -> RgbaImage {
+    RgbaImage::from_fn(top.width(), top.height(), |x, y| {
+        mix(
+            top.get_pixel(x, y),
+            bottom.get_pixel(x, y),
+            weights.get_pixel(x, y)[0],
+        )
+    })
+}
+
+/// Laplacian pyramid blending, the seam is placed halfway through the feather ramp and every
+/// band of the pyramid is blended with a mask blurred to the same scale.
+fn multi_band(top: &RgbaImage, bottom: &RgbaImage, weights: &WeightMap, width: u32) -> RgbaImage {
+    // The seam sits halfway through the ramp and each level roughly doubles how far the mask
+    // is blurred, so stop before the coarsest band would reach past the edges of the ramp.
+    let levels = width
+        .ilog2()
+        .saturating_sub(2)
+        .min(top.width().min(top.height()).max(1).ilog2());
+
+    // Transparent pixels would bleed into their neighbours once blurred, so fill them with
+    // whatever the other image has at the same spot. Values are kept in `0..=1` since that is
+    // the range `resize` clamps floating point pixels to.
+    let as_float = |image: &RgbaImage, other: &RgbaImage| {
+        Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+            let pixel = match image.get_pixel(x, y) {
+                pixel if pixel[3] == 0 => other.get_pixel(x, y),
+                pixel => pixel,
+            };
+
+            Rgba(pixel.0.map(|channel| channel as f32 / 255.0))
+        })
+    };
+
+    let mask = WeightMap::from_fn(weights.width(), weights.height(), |x, y| {
+        Luma([(weights.get_pixel(x, y)[0] >= 0.5) as u8 as f32])
+    });
+
+    let top_pyramid = laplacian_pyramid(as_float(top, bottom), levels);
+    let bottom_pyramid = laplacian_pyramid(as_float(bottom, top), levels);
+    let mask_pyramid = gaussian_pyramid(mask, levels);
+
+    let mut bands = top_pyramid
+        .into_iter()
+        .zip(bottom_pyramid)
+        .zip(mask_pyramid)
+        .map(|((mut top_band, bottom_band), mask)| {
+            for ((top_pixel, bottom_pixel), weight) in top_band
+                .pixels_mut()
+                .zip(bottom_band.pixels())
+                .zip(mask.pixels())
+            {
+                for channel in 0..4 {
+                    top_pixel[channel] =
+                        top_pixel[channel] * (1.0 - weight[0]) + bottom_pixel[channel] * weight[0];
+                }
+            }
+
+            top_band
+        })
+        .collect::<Vec<_>>();
+
+    let mut collapsed = bands.pop().expect("pyramid has at least one level");
+
+    for band in bands.into_iter().rev() {
+        let mut upscaled = resize(
+            &collapsed,
+            band.width(),
+            band.height(),
+            FilterType::Triangle,
+        );
+
+        for (pixel, band_pixel) in upscaled.pixels_mut().zip(band.pixels()) {
+            for channel in 0..4 {
+                pixel[channel] += band_pixel[channel];
+            }
+        }
+
+        collapsed = upscaled;
+    }
+
+    RgbaImage::from_fn(collapsed.width(), collapsed.height(), |x, y| {
+        Rgba(
+            collapsed
+                .get_pixel(x, y)
+                .0
+                .map(|channel| (channel * 255.0).round().clamp(0.0, 255.0) as u8),
+        )
+    })
+}
+
+fn half_size<P>(image: &ImageBuffer<P, Vec<f32>>) -> ImageBuffer<P, Vec<f32>>
+where
+    P: image::Pixel<Subpixel = f32> + 'static,
+{
+    resize(
+        image,
+        (image.width() / 2).max(1),
+        (image.height() / 2).max(1),
+        FilterType::Triangle,
+    )
+}
+
+fn gaussian_pyramid(mask: WeightMap, levels: u32) -> Vec<WeightMap> {
+    let mut pyramid = vec![mask];
+
+    for _ in 0..levels {
+        let next = half_size(pyramid.last().expect("has base level"));
+        pyramid.push(next);
+    }
+
+    pyramid
+}
+
+fn laplacian_pyramid(image: Rgba32FImage, levels: u32) -> Vec<Rgba32FImage> {
+    let mut pyramid = Vec::with_capacity(levels as usize + 1);
+    let mut current = image;
+
+    for _ in 0..levels {
+        let smaller = half_size(&current);
+        let upscaled = resize(
+            &smaller,
+            current.width(),
+            current.height(),
+            FilterType::Triangle,
+        );
+
+        for (pixel, upscaled_pixel) in current.pixels_mut().zip(upscaled.pixels()) {
+            for channel in 0..4 {
+                pixel[channel] -= upscaled_pixel[channel];
+            }
+        }
+
+        pyramid.push(current);
+        current = smaller;
+    }
+
+    pyramid.push(current);
+    pyramid
+}
@@ -1,8 +1,14 @@
+use std::{sync::OnceLock, time::Duration};
+
 use image::RgbaImage;
 
-use crate::error::MissingFieldError;
+use crate::{error::MissingFieldError, util::time::Stopwatch};
 
-use super::{CheckDirection, ImageStitcher, MatchMode, Order};
+use super::{
+    Align, BlendMode, CancelToken, CheckDirection, Color, CompositeOptions,
+    DEFAULT_ALPHA_THRESHOLD, Exclusions, ImageStitcher, LowConfidence, MatchMode, Observer,
+    OffsetHints, Order, ScaleMode, SeamMode, SearchMode, StickyMode, StitchSession, Threads,
+};
 
 #[derive(Debug, Default)]
 pub struct ImageStitcherBuilder {
@@ -12,6 +18,27 @@ pub struct ImageStitcherBuilder {
     window_size: Option<usize>,
     match_mode: Option<MatchMode>,
     crop: Option<u32>,
+    search_mode: Option<SearchMode>,
+    blend: Option<BlendMode>,
+    seam: Option<SeamMode>,
+    align: Option<Align>,
+    background: Option<Color>,
+    padding: Option<u32>,
+    gap: Option<u32>,
+    separator: Option<Color>,
+    min_confidence: Option<f64>,
+    low_confidence: Option<LowConfidence>,
+    alpha_threshold: Option<u8>,
+    exclusions: Option<Exclusions>,
+    sticky: Option<StickyMode>,
+    offset_hints: Option<OffsetHints>,
+    grid_columns: Option<usize>,
+    scale: Option<ScaleMode>,
+    observer: Option<Observer>,
+    cancel: Option<CancelToken>,
+    time_budget: Option<Duration>,
+    threads: Option<Threads>,
+    deterministic: Option<bool>,
 }
 
 impl ImageStitcherBuilder {
@@ -43,6 +70,7 @@ impl ImageStitcherBuilder {
         }
     }
 
+    /// Number of rows compared at every offset, the rows of the window are compared in order.
     #[must_use]
     pub fn window_size<T: Into<Option<usize>>>(self, window_size: T) -> Self {
         Self {
@@ -66,24 +94,256 @@ impl ImageStitcherBuilder {
         }
     }
 
+    #[must_use]
+    pub fn search_mode<T: Into<Option<SearchMode>>>(self, search_mode: T) -> Self {
+        Self {
+            search_mode: search_mode.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn blend<T: Into<Option<BlendMode>>>(self, blend: T) -> Self {
+        Self {
+            blend: blend.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn seam<T: Into<Option<SeamMode>>>(self, seam: T) -> Self {
+        Self {
+            seam: seam.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn align<T: Into<Option<Align>>>(self, align: T) -> Self {
+        Self {
+            align: align.into(),
+            ..self
+        }
+    }
+
+    /// Colour the transparent parts of the stitched image are filled with, left transparent
+    /// when not set.
+    #[must_use]
+    pub fn background<T: Into<Option<Color>>>(self, background: T) -> Self {
+        Self {
+            background: background.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn padding<T: Into<Option<u32>>>(self, padding: T) -> Self {
+        Self {
+            padding: padding.into(),
+            ..self
+        }
+    }
+
+    /// Space left between neighbouring images instead of overlapping them.
+    #[must_use]
+    pub fn gap<T: Into<Option<u32>>>(self, gap: T) -> Self {
+        Self {
+            gap: gap.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn separator<T: Into<Option<Color>>>(self, separator: T) -> Self {
+        Self {
+            separator: separator.into(),
+            ..self
+        }
+    }
+
+    /// Minimum [`MatchConfidence::confidence`](super::MatchConfidence) every seam has to reach,
+    /// see [`ImageStitcherBuilder::low_confidence`] for what happens when one does not.
+    #[must_use]
+    pub fn min_confidence<T: Into<Option<f64>>>(self, min_confidence: T) -> Self {
+        Self {
+            min_confidence: min_confidence.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn low_confidence<T: Into<Option<LowConfidence>>>(self, low_confidence: T) -> Self {
+        Self {
+            low_confidence: low_confidence.into(),
+            ..self
+        }
+    }
+
+    /// Pixels with an alpha below the threshold are left out when matching, `0` matches every
+    /// pixel. Defaults to [`DEFAULT_ALPHA_THRESHOLD`].
+    #[must_use]
+    pub fn alpha_threshold<T: Into<Option<u8>>>(self, alpha_threshold: T) -> Self {
+        Self {
+            alpha_threshold: alpha_threshold.into(),
+            ..self
+        }
+    }
+
+    /// Areas left out when matching, either the same for every image or given per image. The
+    /// match window moves down past rows of the second image that are left out entirely.
+    #[must_use]
+    pub fn exclusions<T: Into<Option<Exclusions>>>(self, exclusions: T) -> Self {
+        Self {
+            exclusions: exclusions.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn sticky<T: Into<Option<StickyMode>>>(self, sticky: T) -> Self {
+        Self {
+            sticky: sticky.into(),
+            ..self
+        }
+    }
+
+    /// Limits the offsets searched between each pair of neighbouring images, only used for
+    /// [`Order::Ordered`] and sessions. A hint that leaves no offset where the images overlap
+    /// fails with [`crate::error::StitchError::HintOutOfRange`].
+    #[must_use]
+    pub fn offset_hints<T: Into<Option<OffsetHints>>>(self, offset_hints: T) -> Self {
+        Self {
+            offset_hints: offset_hints.into(),
+            ..self
+        }
+    }
+
+    /// Number of tiles in every row of an [`Order::Grid`], picked from how well the tiles match
+    /// when not set.
+    #[must_use]
+    pub fn grid_columns<T: Into<Option<usize>>>(self, grid_columns: T) -> Self {
+        Self {
+            grid_columns: grid_columns.into(),
+            ..self
+        }
+    }
+
+    /// How images captured at different zoom levels are brought to the same scale, not used by
+    /// sessions.
+    #[must_use]
+    pub fn scale<T: Into<Option<ScaleMode>>>(self, scale: T) -> Self {
+        Self {
+            scale: scale.into(),
+            ..self
+        }
+    }
+
+    /// Gets told which pairs of images are being matched, how far along the search for each one
+    /// is and when compositing starts and ends.
+    #[must_use]
+    pub fn observer<T: Into<Option<Observer>>>(self, observer: T) -> Self {
+        Self {
+            observer: observer.into(),
+            ..self
+        }
+    }
+
+    /// Stops the stitch with [`crate::error::StitchError::Cancelled`] once it is cancelled.
+    #[must_use]
+    pub fn cancel<T: Into<Option<CancelToken>>>(self, cancel: T) -> Self {
+        Self {
+            cancel: cancel.into(),
+            ..self
+        }
+    }
+
+    /// How long matching may take, for every pushed image with sessions. Once it runs out the
+    /// searches stop and use the best offset found so far, marking the seam as
+    /// [`super::MatchConfidence::truncated`]. Every pair still gets at least the coarsest level of
+    /// [`super::SearchMode::Pyramid`] scored, so a budget of zero gives a quick rough stitch.
+    #[must_use]
+    pub fn time_budget<T: Into<Option<Duration>>>(self, time_budget: T) -> Self {
+        Self {
+            time_budget: time_budget.into(),
+            ..self
+        }
+    }
+
+    /// Threads the searches run on, defaults to [`Threads::Global`].
+    #[must_use]
+    pub fn threads<T: Into<Option<Threads>>>(self, threads: T) -> Self {
+        Self {
+            threads: threads.into(),
+            ..self
+        }
+    }
+
+    /// Breaks ties between equally good offsets by picking the one overlapping the previous image
+    /// the least, so the same images always stitch the same way. A time budget still cuts the
+    /// searches short depending on how fast they run.
+    #[must_use]
+    pub fn deterministic<T: Into<Option<bool>>>(self, deterministic: T) -> Self {
+        Self {
+            deterministic: deterministic.into(),
+            ..self
+        }
+    }
+
+    /// Builds a [`StitchSession`] to push the images to one at a time, the images and order set
+    /// on the builder are not used.
+    pub fn build_session(self) -> Result<StitchSession, MissingFieldError> {
+        Self {
+            images: Some(Vec::new()),
+            order: Some(Order::Ordered),
+            ..self
+        }
+        .build()
+        .map(StitchSession::new)
+    }
+
     pub fn build(self) -> Result<ImageStitcher, MissingFieldError> {
         macro_rules! builder_field_unwrap {
             ($field: ident) => {
                 self.$field
                     .ok_or_else(|| crate::error::MissingFieldError(stringify!($field).into()))?
             };
-            ($field: ident, $default: literal) => {
+            ($field: ident, $default: expr) => {
                 self.$field.unwrap_or_else(|| $default)
             };
         }
 
-        Ok(ImageStitcher::new(
-            builder_field_unwrap!(images),
-            builder_field_unwrap!(order),
-            builder_field_unwrap!(direction),
-            builder_field_unwrap!(window_size),
-            builder_field_unwrap!(match_mode),
-            builder_field_unwrap!(crop, 0),
-        ))
+        Ok(ImageStitcher {
+            images: builder_field_unwrap!(images),
+            order: builder_field_unwrap!(order),
+            direction: builder_field_unwrap!(direction),
+            window_size: builder_field_unwrap!(window_size),
+            match_mode: builder_field_unwrap!(match_mode),
+            crop: builder_field_unwrap!(crop, 0),
+            search_mode: builder_field_unwrap!(search_mode, SearchMode::Pyramid),
+            composite: CompositeOptions {
+                blend: builder_field_unwrap!(blend, BlendMode::HardCut),
+                seam: builder_field_unwrap!(seam, SeamMode::Straight),
+                align: builder_field_unwrap!(align, Align::Content),
+                background: self.background,
+                padding: builder_field_unwrap!(padding, 0),
+                gap: builder_field_unwrap!(gap, 0),
+                separator: self.separator,
+            },
+            min_confidence: self.min_confidence,
+            low_confidence: builder_field_unwrap!(low_confidence, LowConfidence::Fail),
+            alpha_threshold: builder_field_unwrap!(alpha_threshold, DEFAULT_ALPHA_THRESHOLD),
+            exclusions: builder_field_unwrap!(exclusions, Exclusions::default()),
+            sticky: builder_field_unwrap!(sticky, StickyMode::Off),
+            offset_hints: builder_field_unwrap!(offset_hints, OffsetHints::default()),
+            grid_columns: self.grid_columns,
+            scale: builder_field_unwrap!(scale, ScaleMode::Off),
+            observer: self.observer,
+            cancel: self.cancel,
+            time_budget: self.time_budget,
+            clock: Stopwatch::start(),
+            threads: builder_field_unwrap!(threads, Threads::Global),
+            single_thread: OnceLock::new(),
+            deterministic: builder_field_unwrap!(deterministic, false),
+        })
     }
 }
@@ -1,9 +1,18 @@
+mod blend;
 pub mod builder;
+pub mod cancel;
+pub mod observer;
 pub mod params;
+mod phase;
+mod scan;
+pub mod session;
 pub mod stitcher;
 
 pub use builder::*;
+pub use cancel::*;
+pub use observer::*;
 pub use params::*;
+pub use session::*;
 pub use stitcher::*;
 
 #[cfg(target_arch = "wasm32")]
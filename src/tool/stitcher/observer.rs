@@ -0,0 +1,124 @@
+use std::{fmt, sync::Arc};
+
+use super::{CheckDirection, MatchConfidence};
+
+/// Something that happened while stitching, images are referred to by their index in the input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StitchEvent {
+    /// Started looking for where `second` goes on `first`.
+    PairStarted {
+        first: usize,
+        second: usize,
+    },
+    /// Part of the offsets between `first` and `second` along `direction` were scanned, from 0 to
+    /// 1. [`CheckDirection::Auto`] scans more than one direction, each one starts again from 0.
+    PairProgress {
+        first: usize,
+        second: usize,
+        direction: CheckDirection,
+        progress: f64,
+    },
+    /// Found where `second` goes on `first`.
+    PairFinished {
+        first: usize,
+        second: usize,
+        confidence: MatchConfidence,
+    },
+    CompositingStarted,
+    CompositingFinished,
+}
+
+/// Gets told how a stitch is going. Pairs are matched in parallel, so events of different pairs
+/// can come in from several threads at once and interleave.
+pub trait StitchObserver: Send + Sync {
+    fn on_event(&self, event: StitchEvent);
+}
+
+impl<F: Fn(StitchEvent) + Send + Sync> StitchObserver for F {
+    fn on_event(&self, event: StitchEvent) {
+        self(event)
+    }
+}
+
+/// A shared [`StitchObserver`], so it can be passed to the builder.
+#[derive(Clone)]
+pub struct Observer(pub Arc<dyn StitchObserver>);
+
+impl Observer {
+    pub fn new(observer: impl StitchObserver + 'static) -> Self {
+        Self(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StitchEvent {
+    pub fn to_json(self) -> js_sys::Object {
+        let (kind, obj, pair) = match self {
+            StitchEvent::PairStarted { first, second } => {
+                ("pairStarted", js_sys::Object::new(), Some((first, second)))
+            }
+            StitchEvent::PairProgress {
+                first,
+                second,
+                direction,
+                progress,
+            } => {
+                let obj = js_sys::Object::new();
+
+                js_sys::Reflect::set(
+                    &obj,
+                    &wasm_bindgen::JsValue::from_str("direction"),
+                    &wasm_bindgen::JsValue::from_str(&format!("{:?}", direction)),
+                )
+                .ok();
+                js_sys::Reflect::set(
+                    &obj,
+                    &wasm_bindgen::JsValue::from_str("progress"),
+                    &wasm_bindgen::JsValue::from(progress),
+                )
+                .ok();
+
+                ("pairProgress", obj, Some((first, second)))
+            }
+            StitchEvent::PairFinished {
+                first,
+                second,
+                confidence,
+            } => ("pairFinished", confidence.to_json(), Some((first, second))),
+            StitchEvent::CompositingStarted => ("compositingStarted", js_sys::Object::new(), None),
+            StitchEvent::CompositingFinished => {
+                ("compositingFinished", js_sys::Object::new(), None)
+            }
+        };
+
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("type"),
+            &wasm_bindgen::JsValue::from_str(kind),
+        )
+        .ok();
+
+        if let Some((first, second)) = pair {
+            js_sys::Reflect::set(
+                &obj,
+                &wasm_bindgen::JsValue::from_str("first"),
+                &wasm_bindgen::JsValue::from(first as u32),
+            )
+            .ok();
+            js_sys::Reflect::set(
+                &obj,
+                &wasm_bindgen::JsValue::from_str("second"),
+                &wasm_bindgen::JsValue::from(second as u32),
+            )
+            .ok();
+        }
+
+        obj
+    }
+}
@@ -1,14 +1,29 @@
 use std::{
+    collections::VecDeque,
     ops::{Add, AddAssign, Deref},
     str::FromStr,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
 
-use crate::error::{UnknownError, unknown_error_expected};
+use image::Rgba;
+use rayon::ThreadPool;
+
+use crate::{
+    error::{StitchError, UnknownError, unknown_error_expected},
+    util::image::ImageCrop,
+};
 
 #[derive(Debug, Clone)]
 pub enum MatchMode {
     Normal,
     Edges,
+    /// Estimates the offset with FFT based phase correlation and only scores the strongest
+    /// peaks, so the cost does not grow with the window size or the searched range.
+    PhaseCorrelation,
+    /// Scores windows with zero-mean normalized cross-correlation, which ignores brightness and
+    /// contrast changes between the images.
+    Ncc,
 }
 
 impl FromStr for MatchMode {
@@ -18,19 +33,422 @@ impl FromStr for MatchMode {
         match s {
             "n" | "N" | "Normal" | "normal" => Ok(MatchMode::Normal),
             "e" | "E" | "Edges" | "edges" => Ok(MatchMode::Edges),
+            "pc" | "PC" | "PhaseCorrelation" | "phaseCorrelation" => {
+                Ok(MatchMode::PhaseCorrelation)
+            }
+            "ncc" | "NCC" | "Ncc" => Ok(MatchMode::Ncc),
             value => Err(UnknownError {
                 name: "MatchMode".into(),
                 value: value.into(),
                 expected: unknown_error_expected!(
                     "n" | "N" | "Normal" | "normal" => "Normal",
-                    "e" | "E" | "Edges" | "edges" => "Edges"
+                    "e" | "E" | "Edges" | "edges" => "Edges",
+                    "pc" | "PC" | "PhaseCorrelation" | "phaseCorrelation" => "PhaseCorrelation",
+                    "ncc" | "NCC" | "Ncc" => "Ncc"
                 ),
             }),
         }
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SearchMode {
+    /// Scores every candidate offset at full resolution.
+    Exhaustive,
+    /// Scores candidates on downscaled copies first, then only refines the best ones at each finer level.
+    Pyramid,
+}
+
+impl FromStr for SearchMode {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "e" | "E" | "Exhaustive" | "exhaustive" => Ok(SearchMode::Exhaustive),
+            "p" | "P" | "Pyramid" | "pyramid" => Ok(SearchMode::Pyramid),
+            value => Err(UnknownError {
+                name: "SearchMode".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "e" | "E" | "Exhaustive" | "exhaustive" => "Exhaustive",
+                    "p" | "P" | "Pyramid" | "pyramid" => "Pyramid"
+                ),
+            }),
+        }
+    }
+}
+
+/// Default alpha a pixel needs to take part in matching, only fully transparent pixels are left
+/// out.
+pub const DEFAULT_ALPHA_THRESHOLD: u8 = 1;
+
+/// Default number of pixels a [`BlendMode`] transitions over.
+pub const DEFAULT_BLEND_WIDTH: u32 = 32;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BlendMode {
+    /// Paints the second image over the first one.
+    HardCut,
+    /// Linearly fades from the first image into the second over the given number of pixels.
+    Feather(u32),
+    /// Blends each frequency band over its own width, low frequencies fade over the given number
+    /// of pixels while fine detail switches over close to the seam.
+    MultiBand(u32),
+}
+
+impl BlendMode {
+    pub fn with_width(self, width: u32) -> Self {
+        match self {
+            BlendMode::HardCut => BlendMode::HardCut,
+            BlendMode::Feather(_) => BlendMode::Feather(width),
+            BlendMode::MultiBand(_) => BlendMode::MultiBand(width),
+        }
+    }
+}
+
+impl FromStr for BlendMode {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hc" | "HC" | "HardCut" | "hardCut" => Ok(BlendMode::HardCut),
+            "f" | "F" | "Feather" | "feather" => Ok(BlendMode::Feather(DEFAULT_BLEND_WIDTH)),
+            "mb" | "MB" | "MultiBand" | "multiBand" => {
+                Ok(BlendMode::MultiBand(DEFAULT_BLEND_WIDTH))
+            }
+            value => Err(UnknownError {
+                name: "BlendMode".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "hc" | "HC" | "HardCut" | "hardCut" => "HardCut",
+                    "f" | "F" | "Feather" | "feather" => "Feather",
+                    "mb" | "MB" | "MultiBand" | "multiBand" => "MultiBand"
+                ),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SeamMode {
+    /// Switches between the images along the edge of the second image.
+    Straight,
+    /// Switches between the images along the path through the overlap where they differ the
+    /// least, so content that changed between captures is not cut in half.
+    MinCost,
+}
+
+impl FromStr for SeamMode {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" | "S" | "Straight" | "straight" => Ok(SeamMode::Straight),
+            "mc" | "MC" | "MinCost" | "minCost" => Ok(SeamMode::MinCost),
+            value => Err(UnknownError {
+                name: "SeamMode".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "s" | "S" | "Straight" | "straight" => "Straight",
+                    "mc" | "MC" | "MinCost" | "minCost" => "MinCost"
+                ),
+            }),
+        }
+    }
+}
+
+/// Where an image goes across the stitch direction next to the image before it, left and right
+/// are top and bottom for [`CheckDirection::Horizontal`].
+///
+/// Only used for [`CheckDirection::Vertical`] and [`CheckDirection::Horizontal`] seams, the
+/// sideways directions always follow the content. Sessions match every image against the
+/// stitched image and always use [`Align::Content`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+    /// Wherever the match put it.
+    #[default]
+    Content,
+}
+
+impl FromStr for Align {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "l" | "L" | "Left" | "left" => Ok(Align::Left),
+            "c" | "C" | "Center" | "center" => Ok(Align::Center),
+            "r" | "R" | "Right" | "right" => Ok(Align::Right),
+            "content" | "Content" => Ok(Align::Content),
+            value => Err(UnknownError {
+                name: "Align".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "l" | "L" | "Left" | "left" => "Left",
+                    "c" | "C" | "Center" | "center" => "Center",
+                    "r" | "R" | "Right" | "right" => "Right",
+                    "content" | "Content" => "Content"
+                ),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub Rgba<u8>);
+
+impl FromStr for Color {
+    type Err = UnknownError;
+
+    /// Parses `#rrggbb` or `#rrggbbaa`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let channels = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 || hex.len() == 8)
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|at| {
+                        hex.get(at..at + 2)
+                            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+                    })
+                    .collect::<Option<Vec<_>>>()
+            });
+
+        match channels.as_deref() {
+            Some(&[r, g, b]) => Ok(Color(Rgba([r, g, b, 255]))),
+            Some(&[r, g, b, a]) => Ok(Color(Rgba([r, g, b, a]))),
+            _ => Err(UnknownError {
+                name: "Color".into(),
+                value: s.into(),
+                expected: unknown_error_expected!("#rrggbb" | "#rrggbbaa" => "Color"),
+            }),
+        }
+    }
+}
+
+/// Settings for how two placed images are composited together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompositeOptions {
+    pub blend: BlendMode,
+    pub seam: SeamMode,
+    pub align: Align,
+    /// Colour the transparent parts of the stitched image are filled with.
+    pub background: Option<Color>,
+    /// Space around the stitched image, filled with the background.
+    pub padding: u32,
+    /// Space between neighbouring images, the image before a gap is cut off where the next one
+    /// starts instead of overlapping it. Not used for [`Order::Grid`].
+    pub gap: u32,
+    /// Colour of the gaps, they are filled with the background when not set.
+    pub separator: Option<Color>,
+}
+
+impl Default for CompositeOptions {
+    fn default() -> Self {
+        Self {
+            blend: BlendMode::HardCut,
+            seam: SeamMode::Straight,
+            align: Align::Content,
+            background: None,
+            padding: 0,
+            gap: 0,
+            separator: None,
+        }
+    }
+}
+
+/// Axis aligned rectangle, `x` and `y` are the top left corner.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        match x < right && y < bottom {
+            true => Some(Rect::new(x, y, right - x, bottom - y)),
+            false => None,
+        }
+    }
+}
+
+impl FromStr for Rect {
+    type Err = UnknownError;
+
+    /// Parses `x,y,width,height`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Option<Vec<u32>> = s
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect();
+
+        match values.as_deref() {
+            Some(&[x, y, width, height]) => Ok(Rect::new(x, y, width, height)),
+            _ => Err(UnknownError {
+                name: "Rect".into(),
+                value: s.into(),
+                expected: unknown_error_expected!("x,y,width,height" => "Rect"),
+            }),
+        }
+    }
+}
+
+/// Areas of the images that are left out when matching, they are still composited normally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exclusions {
+    /// The same areas for every image.
+    Shared(Vec<Rect>),
+    /// Areas for each image, in the same order as the images.
+    PerImage(Vec<Vec<Rect>>),
+}
+
+impl Exclusions {
+    pub fn for_image(&self, index: usize) -> &[Rect] {
+        match self {
+            Exclusions::Shared(rects) => rects,
+            Exclusions::PerImage(images) => images.get(index).map_or(&[], Vec::as_slice),
+        }
+    }
+}
+
+impl Default for Exclusions {
+    fn default() -> Self {
+        Exclusions::Shared(Vec::new())
+    }
+}
+
+impl FromStr for Exclusions {
+    type Err = UnknownError;
+
+    /// Parses rects separated by `;`, for example `0,0,40,40;300,600,60,40`. Separating groups
+    /// of them with `|` gives each image its own group, an empty group excludes nothing.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_rects = |rects: &str| {
+            rects
+                .split(';')
+                .filter(|rect| !rect.trim().is_empty())
+                .map(Rect::from_str)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        match s.contains('|') {
+            true => Ok(Exclusions::PerImage(
+                s.split('|').map(parse_rects).collect::<Result<_, _>>()?,
+            )),
+            false => Ok(Exclusions::Shared(parse_rects(s)?)),
+        }
+    }
+}
+
+/// What is known about how far apart two neighbouring images are, the search for their offset
+/// is kept to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OffsetHint {
+    /// The top left corner of the second image is expected at `offset` from the one of the first
+    /// image, give or take `tolerance` pixels. Only `y` is used for [`CheckDirection::Vertical`]
+    /// and only `x` for [`CheckDirection::Horizontal`].
+    Expected { offset: Position, tolerance: u32 },
+    /// The second image covers at least `min` and at most `max` lines of the first image.
+    Overlap { min: u32, max: u32 },
+}
+
+impl FromStr for OffsetHint {
+    type Err = UnknownError;
+
+    /// Parses `x,y~tolerance` or `min..max`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || {
+            let (offset, tolerance) = s.split_once('~')?;
+            let (x, y) = offset.split_once(',')?;
+
+            Some(OffsetHint::Expected {
+                offset: Position {
+                    x: x.trim().parse().ok()?,
+                    y: y.trim().parse().ok()?,
+                },
+                tolerance: tolerance.trim().parse().ok()?,
+            })
+        };
+        let overlap = || {
+            let (min, max) = s.split_once("..")?;
+
+            Some(OffsetHint::Overlap {
+                min: min.trim().parse().ok()?,
+                max: max.trim().parse().ok()?,
+            })
+        };
+
+        expected().or_else(overlap).ok_or_else(|| UnknownError {
+            name: "OffsetHint".into(),
+            value: s.into(),
+            expected: unknown_error_expected!(
+                "x,y~tolerance" => "Expected",
+                "min..max" => "Overlap"
+            ),
+        })
+    }
+}
+
+/// An optional [`OffsetHint`] for every pair of neighbouring images, in the same order as the
+/// images. Pairs past the end have no hint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetHints(pub Vec<Option<OffsetHint>>);
+
+impl OffsetHints {
+    pub fn for_pair(&self, index: usize) -> Option<&OffsetHint> {
+        self.0.get(index).and_then(Option::as_ref)
+    }
+}
+
+impl FromStr for OffsetHints {
+    type Err = UnknownError;
+
+    /// Parses hints separated by `;`, for example `0,350~20;;300..500`. An empty hint leaves
+    /// that pair unconstrained.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .map(|hint| match hint.trim().is_empty() {
+                true => Ok(None),
+                false => OffsetHint::from_str(hint).map(Some),
+            })
+            .collect::<Result<_, _>>()
+            .map(OffsetHints)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -92,11 +510,442 @@ impl Position {
     }
 }
 
+/// Where [`super::ImageStitcher::composite`] draws an image, placements are drawn in order with
+/// later ones on top.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// Index of the drawn image.
+    pub index: usize,
+    /// Left of the drawn part of the image, relative to the left of the stitched image. Negative
+    /// when a later crop cuts it off.
+    pub x: i32,
+    /// Top of the drawn part of the image, relative to the top of the stitched image.
+    pub y: i32,
+    /// Part of the image that is drawn.
+    pub crop: ImageCrop,
+    /// Cut off everything drawn so far before this image is drawn, the way stitching crops the
+    /// stitched image before adding the next one to it.
+    pub canvas_crop: ImageCrop,
+}
+
+/// Where an input image ended up in the stitched image.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct InputLayout {
+    /// Position of the image in the sequence the images were stitched in, the same as its index
+    /// unless [`Order::Unordered`] found another one.
+    pub sequence: usize,
+    /// Left of the whole image before any cropping, relative to the left of the stitched image.
+    pub x: i32,
+    /// Top of the whole image before any cropping, relative to the top of the stitched image.
+    pub y: i32,
+    /// Size of the image once scaled.
+    pub width: u32,
+    pub height: u32,
+    /// Factor the image was resampled by before matching, `1` unless [`ScaleMode`] changed it.
+    pub scale: f64,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl InputLayout {
+    pub fn to_json(self) -> js_sys::Object {
+        let obj = js_sys::Object::new();
+
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("sequence"),
+            &wasm_bindgen::JsValue::from(self.sequence as u32),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("x"),
+            &wasm_bindgen::JsValue::from(self.x),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("y"),
+            &wasm_bindgen::JsValue::from(self.y),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("width"),
+            &wasm_bindgen::JsValue::from(self.width),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("height"),
+            &wasm_bindgen::JsValue::from(self.height),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("scale"),
+            &wasm_bindgen::JsValue::from(self.scale),
+        )
+        .ok();
+
+        obj
+    }
+}
+
+/// How much a found match can be trusted.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MatchConfidence {
+    /// Mean per-pixel difference of the match, from `0` for identical pixels up to `255`. With
+    /// [`MatchMode::Ncc`] it is the root mean square difference once the brightness difference
+    /// between the images is removed.
+    pub mean_difference: f64,
+    /// Ratio between the mean difference of the best match and the best clearly different
+    /// offset, close to `1` when repetitive content makes several offsets match equally well.
+    /// With [`MatchMode::Ncc`] it compares how far their correlations are from a perfect one.
+    pub ambiguity: f64,
+    /// Combination of the two above, from `0` for a guess up to `1` for an exact unique match.
+    /// With [`MatchMode::Ncc`] the correlation takes the place of the mean difference, so a
+    /// brightness or contrast change does not lower it.
+    pub confidence: f64,
+    /// Set when the confidence fell below the minimum and [`LowConfidence::Flag`] was used.
+    pub flagged: bool,
+    /// Set when the time budget ran out before every offset was searched, the match is the best
+    /// one found until then.
+    pub truncated: bool,
+    /// Direction the seam was stitched in, picked per seam with [`CheckDirection::Auto`].
+    pub direction: CheckDirection,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl MatchConfidence {
+    pub fn to_json(self) -> js_sys::Object {
+        let obj = js_sys::Object::new();
+
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("meanDifference"),
+            &wasm_bindgen::JsValue::from(self.mean_difference),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("ambiguity"),
+            &wasm_bindgen::JsValue::from(self.ambiguity),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("confidence"),
+            &wasm_bindgen::JsValue::from(self.confidence),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("flagged"),
+            &wasm_bindgen::JsValue::from(self.flagged),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("truncated"),
+            &wasm_bindgen::JsValue::from(self.truncated),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("direction"),
+            &wasm_bindgen::JsValue::from_str(&format!("{:?}", self.direction)),
+        )
+        .ok();
+
+        obj
+    }
+}
+
+/// A seam between two images that were stitched next to each other.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SeamReport {
+    /// Index of the image the seam was stitched onto.
+    pub first: usize,
+    /// Index of the image that was stitched on.
+    pub second: usize,
+    /// Match score of the offset, higher is better. Scores only compare between seams matched
+    /// with the same [`MatchMode`] and window size.
+    pub score: u64,
+    /// Set when the second image was given before the first one and [`Order::Unordered`] put
+    /// them the other way around.
+    pub flipped: bool,
+    pub confidence: MatchConfidence,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl SeamReport {
+    pub fn to_json(self) -> js_sys::Object {
+        let obj = self.confidence.to_json();
+
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("first"),
+            &wasm_bindgen::JsValue::from(self.first as u32),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("second"),
+            &wasm_bindgen::JsValue::from(self.second as u32),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("score"),
+            &wasm_bindgen::JsValue::from(self.score as f64),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("flipped"),
+            &wasm_bindgen::JsValue::from(self.flipped),
+        )
+        .ok();
+
+        obj
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LowConfidence {
+    /// Stop stitching with [`crate::error::StitchError::LowConfidence`].
+    Fail,
+    /// Keep stitching and set [`MatchConfidence::flagged`] on the seam.
+    Flag,
+}
+
+impl FromStr for LowConfidence {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" | "Fail" => Ok(LowConfidence::Fail),
+            "flag" | "Flag" => Ok(LowConfidence::Flag),
+            value => Err(UnknownError {
+                name: "LowConfidence".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "fail" | "Fail" => "Fail",
+                    "flag" | "Flag" => "Flag"
+                ),
+            }),
+        }
+    }
+}
+
+/// Threads the searches of a stitch run on.
+#[derive(Debug, Default, Clone)]
+pub enum Threads {
+    /// The global rayon thread pool.
+    #[default]
+    Global,
+    /// A thread pool of the caller, so stitching does not take over the global one.
+    Pool(Arc<ThreadPool>),
+    /// A single thread, every offset is scored one after another. The thread is started once
+    /// for every stitcher or session and kept for as long as it lives.
+    Single,
+}
+
+impl Threads {
+    /// Runs `run` on the threads. The thread of [`Threads::Single`] is kept in `single`, so it is
+    /// only started the first time.
+    pub(super) fn install<R: Send>(
+        &self,
+        #[cfg_attr(
+            all(target_arch = "wasm32", not(target_feature = "atomics")),
+            allow(unused_variables)
+        )]
+        single: &OnceLock<ThreadPool>,
+        run: impl FnOnce() -> R + Send,
+    ) -> Result<R, StitchError> {
+        match self {
+            Threads::Global => Ok(run()),
+            Threads::Pool(pool) => Ok(pool.install(run)),
+            // Without threads there is nothing to start, everything runs on the calling thread.
+            #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+            Threads::Single => Ok(run()),
+            #[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+            Threads::Single => {
+                let pool = match single.get() {
+                    Some(pool) => pool,
+                    None => {
+                        let pool = rayon::ThreadPoolBuilder::new()
+                            .num_threads(1)
+                            .build()
+                            .map_err(|err| StitchError::ThreadPool(err.to_string()))?;
+
+                        single.get_or_init(|| pool)
+                    }
+                };
+
+                Ok(pool.install(run))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScaleMode {
+    /// Images are matched and stitched at the size they were given.
+    Off,
+    /// Every image is resampled so its breadth, the width or the height for
+    /// [`CheckDirection::Horizontal`], matches the first image.
+    Breadth,
+    /// Same as [`ScaleMode::Breadth`], then tries common zoom and device pixel ratio changes for
+    /// every image against the one before it and keeps the most confident match. Only
+    /// [`Order::Ordered`] has neighbours to estimate against, the others just match the breadth.
+    Auto,
+}
+
+impl FromStr for ScaleMode {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "Off" => Ok(ScaleMode::Off),
+            "breadth" | "Breadth" => Ok(ScaleMode::Breadth),
+            "auto" | "Auto" => Ok(ScaleMode::Auto),
+            value => Err(UnknownError {
+                name: "ScaleMode".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "off" | "Off" => "Off",
+                    "breadth" | "Breadth" => "Breadth",
+                    "auto" | "Auto" => "Auto"
+                ),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StickyMode {
+    /// Sticky headers and footers are matched and stitched like the rest of the images.
+    Off,
+    /// Rows that stay the same at the start and end of every image are found and left out of
+    /// matching, keeping them only once at the start and end of the stitched image. Only applies
+    /// to [`CheckDirection::Vertical`] and [`CheckDirection::Horizontal`], and not to
+    /// [`Order::Grid`].
+    Auto,
+}
+
+impl FromStr for StickyMode {
+    type Err = UnknownError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "Off" => Ok(StickyMode::Off),
+            "auto" | "Auto" => Ok(StickyMode::Auto),
+            value => Err(UnknownError {
+                name: "StickyMode".into(),
+                value: value.into(),
+                expected: unknown_error_expected!(
+                    "off" | "Off" => "Off",
+                    "auto" | "Auto" => "Auto"
+                ),
+            }),
+        }
+    }
+}
+
+/// Sticky bands removed by [`StickyMode::Auto`], in rows along the stitch direction, so columns
+/// for [`CheckDirection::Horizontal`].
+///
+/// The header is kept from the first image and the footer from the last one, stitch positions
+/// are relative to the images with both bands removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StickyBands {
+    pub header: u32,
+    pub footer: u32,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StickyBands {
+    pub fn to_json(self) -> js_sys::Object {
+        let obj = js_sys::Object::new();
+
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("header"),
+            &wasm_bindgen::JsValue::from(self.header),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("footer"),
+            &wasm_bindgen::JsValue::from(self.footer),
+        )
+        .ok();
+
+        obj
+    }
+}
+
+/// How long the phases of [`super::ImageStitcher::stitch`] took.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StitchTimings {
+    /// Finding the sticky bands and matching the images.
+    pub matching: Duration,
+    /// Laying out and drawing the stitched image.
+    pub compositing: Duration,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StitchTimings {
+    pub fn to_json(self) -> js_sys::Object {
+        let obj = js_sys::Object::new();
+
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("matchingMs"),
+            &wasm_bindgen::JsValue::from(self.matching.as_secs_f64() * 1000.0),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &wasm_bindgen::JsValue::from_str("compositingMs"),
+            &wasm_bindgen::JsValue::from(self.compositing.as_secs_f64() * 1000.0),
+        )
+        .ok();
+
+        obj
+    }
+}
+
+/// Everything [`super::ImageStitcher::stitch`] worked out on the way to the stitched image.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StitchReport {
+    /// Where every input image ended up, in the same order as the images.
+    pub inputs: Vec<InputLayout>,
+    /// Every seam in the order they were made, which follows the sequence of the images.
+    pub seams: Vec<SeamReport>,
+    /// Direction the stitcher was given, the one every seam was stitched in is in
+    /// [`MatchConfidence::direction`].
+    pub direction: CheckDirection,
+    /// Rows cut off both images along every seam.
+    pub crop: u32,
+    pub sticky_bands: StickyBands,
+    /// Offset of every image against everything stitched before it, the way
+    /// [`super::StitchSession`] reports them.
+    pub positions: VecDeque<Position>,
+    /// Can be changed and passed to [`super::ImageStitcher::composite`] with the same images,
+    /// scaled by [`InputLayout::scale`], to draw the stitched image again. They are relative to
+    /// the image before [`super::ImageStitcher::fill_canvas`] adds the padding.
+    pub placements: Vec<Placement>,
+    pub timings: StitchTimings,
+}
+
 #[derive(Debug, Default)]
 pub struct OverlapScore {
     pub score: u64,
     pub flipped: bool,
     pub position: Position,
+    pub confidence: MatchConfidence,
 }
 
 impl Deref for OverlapScore {
@@ -107,13 +956,18 @@ impl Deref for OverlapScore {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum CheckDirection {
+    #[default]
     Vertical,
     Horizontal,
     Sideways,
     SidewaysRight,
     SidewaysLeft,
+    /// Tries [`CheckDirection::Vertical`] and [`CheckDirection::Horizontal`] for every pair,
+    /// along with [`CheckDirection::Sideways`] when neither matches well, and keeps the most
+    /// confident one. The direction that was used is in [`MatchConfidence::direction`].
+    Auto,
 }
 
 impl FromStr for CheckDirection {
@@ -126,6 +980,7 @@ impl FromStr for CheckDirection {
             "s" | "S" | "Sideways" | "sideways" => Ok(CheckDirection::Sideways),
             "sr" | "SR" | "SidewaysRight" | "sidewaysRight" => Ok(CheckDirection::SidewaysRight),
             "sl" | "SL" | "SidewaysLeft" | "sidewaysLeft" => Ok(CheckDirection::SidewaysLeft),
+            "a" | "A" | "Auto" | "auto" => Ok(CheckDirection::Auto),
             value => Err(UnknownError {
                 name: "CheckDirection".into(),
                 value: value.into(),
@@ -134,7 +989,8 @@ impl FromStr for CheckDirection {
                     "h" | "H" | "Horizontal" | "horizontal" => "Horizontal",
                     "s" | "S" | "Sideways" | "sideways" => "Sideways",
                     "sr" | "SR" | "SidewaysRight" | "sidewaysRight" => "SidewaysRight",
-                    "sl" | "SL" | "SidewaysLeft" | "sidewaysLeft" => "SidewaysLeft"
+                    "sl" | "SL" | "SidewaysLeft" | "sidewaysLeft" => "SidewaysLeft",
+                    "a" | "A" | "Auto" | "auto" => "Auto"
                 ),
             }),
         }
@@ -145,6 +1001,11 @@ impl FromStr for CheckDirection {
 pub enum Order {
     Ordered,
     Unordered,
+    /// Tiles of a grid given row by row. Every tile is matched with the one to its right like
+    /// [`CheckDirection::Horizontal`] and the one below it like [`CheckDirection::Vertical`], the
+    /// direction itself is not used. The number of columns is picked from the ones the number of
+    /// tiles allows unless it is set on the builder.
+    Grid,
 }
 
 impl FromStr for Order {
@@ -154,12 +1015,14 @@ impl FromStr for Order {
         match s {
             "o" | "O" | "Ordered" | "ordered" => Ok(Order::Ordered),
             "u" | "U" | "Unordered" | "unordered" => Ok(Order::Unordered),
+            "g" | "G" | "Grid" | "grid" => Ok(Order::Grid),
             value => Err(UnknownError {
                 name: "Order".into(),
                 value: value.into(),
                 expected: unknown_error_expected!(
                     "o" | "O" | "Ordered" | "ordered" => "Ordered",
-                    "u" | "U" | "Unordered" | "unordered" => "Unordered"
+                    "u" | "U" | "Unordered" | "unordered" => "Unordered",
+                    "g" | "G" | "Grid" | "grid" => "Grid"
                 ),
             }),
         }
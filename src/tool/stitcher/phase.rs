@@ -0,0 +1,221 @@
+use image::GrayImage;
+use itertools::Itertools;
+use rayon::{iter::ParallelIterator, slice::ParallelSliceMut};
+use rustfft::{FftDirection, FftPlanner, num_complex::Complex32};
+
+/// Number of correlation values looked at when picking peaks, several of them usually belong to
+/// the same peak.
+const PEAK_POOL: usize = 64;
+/// Peaks closer than this to an already picked one are treated as part of it.
+const PEAK_RADIUS: i32 = 2;
+/// Width of the border the planes are faded out over, without it the hard edges of the images
+/// correlate strongest with the images lined up on top of each other. Along the rows it is kept
+/// to a quarter of the smallest overlap searched, so a small overlap is not faded out entirely.
+const TAPER_WIDTH: u32 = 32;
+
+/// Estimates where `second` sits relative to `first` with phase correlation, returning up to
+/// `count` of the strongest `(row, horizontal)` offsets inside the given inclusive ranges,
+/// strongest first.
+///
+/// Only the first `first_rows` rows of `first` and the rows of `second` starting at
+/// `second_start` are used, offsets are where row `second_start` of `second` lands on `first`.
+/// Pixels marked with `0` in a plane's mask are left out.
+pub(crate) fn phase_correlation_peaks(
+    (first, first_mask): (&GrayImage, Option<&GrayImage>),
+    first_rows: u32,
+    (second, second_mask): (&GrayImage, Option<&GrayImage>),
+    second_start: u32,
+    rows: (i32, i32),
+    horizontal: (i32, i32),
+    count: usize,
+) -> Vec<(i32, i32)> {
+    let second_rows = second.height() - second_start;
+    let smallest_overlap = (first_rows as i32 - rows.1).min(second_rows as i32 + rows.0);
+    let row_taper = TAPER_WIDTH.min(smallest_overlap.max(0) as u32 / 4);
+
+    // Padding to the combined size keeps every offset where the images still overlap distinct,
+    // the width only needs it when the images can move sideways.
+    let height = (first_rows + second_rows) as usize;
+    let width = match horizontal {
+        (0, 0) => first.width().max(second.width()),
+        _ => first.width() + second.width(),
+    } as usize;
+
+    let mut planner = FftPlanner::<f32>::new();
+
+    let mut first_spectrum = padded_plane(
+        (first, first_mask),
+        (0, first_rows),
+        row_taper,
+        width,
+        height,
+    );
+    let mut second_spectrum = padded_plane(
+        (second, second_mask),
+        (second_start, second_rows),
+        row_taper,
+        width,
+        height,
+    );
+
+    fft_2d(
+        &mut planner,
+        &mut first_spectrum,
+        width,
+        FftDirection::Forward,
+    );
+    fft_2d(
+        &mut planner,
+        &mut second_spectrum,
+        width,
+        FftDirection::Forward,
+    );
+
+    let mut correlation = first_spectrum
+        .iter()
+        .zip(&second_spectrum)
+        .map(|(first, second)| {
+            let cross = first * second.conj();
+            let norm = cross.norm();
+
+            match norm > f32::EPSILON {
+                true => cross / norm,
+                false => Complex32::default(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    fft_2d(&mut planner, &mut correlation, width, FftDirection::Inverse);
+
+    let to_offset = |index: usize| {
+        let (row, column) = ((index / width) as i32, (index % width) as i32);
+
+        let y = match row < first_rows as i32 {
+            true => row,
+            false => row - height as i32,
+        };
+        let x = match column < first.width() as i32 {
+            true => column,
+            false => column - width as i32,
+        };
+
+        (y, x)
+    };
+
+    let pool = correlation
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (to_offset(index), value.re))
+        .filter(|((y, x), _)| {
+            (rows.0..=rows.1).contains(y) && (horizontal.0..=horizontal.1).contains(x)
+        })
+        .k_largest_by(PEAK_POOL, |(_, first), (_, second)| first.total_cmp(second));
+
+    let mut peaks: Vec<(i32, i32)> = Vec::with_capacity(count);
+
+    for ((y, x), _) in pool {
+        if peaks.len() == count {
+            break;
+        }
+
+        let near_picked = peaks
+            .iter()
+            .any(|(py, px)| (y - py).abs().max((x - px).abs()) <= PEAK_RADIUS);
+
+        if !near_picked {
+            peaks.push((y, x));
+        }
+    }
+
+    peaks
+}
+
+/// Writes the gradient of `rows` rows of the plane starting at `start` into the top left of a
+/// zeroed `width * height` buffer, as `horizontal + vertical * i`. The rows are faded out over
+/// `row_taper` rows at the top and bottom.
+///
+/// Correlating gradients instead of the values keeps smooth backgrounds from dominating the
+/// peaks, and fading them out towards the edges keeps the padding from standing out. Gradients
+/// touching a masked pixel are left out, they would only trace the outline of the mask.
+fn padded_plane(
+    (plane, mask): (&GrayImage, Option<&GrayImage>),
+    (start, rows): (u32, u32),
+    row_taper: u32,
+    width: usize,
+    height: usize,
+) -> Vec<Complex32> {
+    let plane_width = plane.width() as usize;
+    let rows = rows as usize;
+    let values =
+        &plane.as_raw()[start as usize * plane_width..(start as usize + rows) * plane_width];
+    let value = |x: usize, y: usize| values[y * plane_width + x] as f32;
+    let counted = |x: usize, y: usize| {
+        mask.is_none_or(|mask| mask.as_raw()[(start as usize + y) * plane_width + x] != 0)
+    };
+
+    let taper = |position: usize, length: usize, width: u32| {
+        let distance = position.min(length - 1 - position) as f32;
+        let width = width.min(length as u32 / 4).max(1) as f32;
+
+        match distance < width {
+            true => 0.5 - 0.5 * (std::f32::consts::PI * (distance + 0.5) / width).cos(),
+            false => 1.0,
+        }
+    };
+
+    let mut buffer = vec![Complex32::default(); width * height];
+
+    for (y, row) in buffer.chunks_exact_mut(width).take(rows).enumerate() {
+        let row_weight = taper(y, rows, row_taper);
+
+        for (x, target) in row.iter_mut().take(plane_width).enumerate() {
+            let (left, right) = (x.saturating_sub(1), (x + 1).min(plane_width - 1));
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(rows - 1));
+
+            if ![(left, y), (right, y), (x, up), (x, down)]
+                .into_iter()
+                .all(|(x, y)| counted(x, y))
+            {
+                continue;
+            }
+
+            let horizontal = value(right, y) - value(left, y);
+            let vertical = value(x, down) - value(x, up);
+            let weight = row_weight * taper(x, plane_width, TAPER_WIDTH);
+
+            *target = Complex32::new(horizontal * weight, vertical * weight);
+        }
+    }
+
+    buffer
+}
+
+/// Transforms the rows and then the columns of a row major `width` wide buffer in place.
+fn fft_2d(
+    planner: &mut FftPlanner<f32>,
+    buffer: &mut [Complex32],
+    width: usize,
+    direction: FftDirection,
+) {
+    let height = buffer.len() / width;
+
+    let row_fft = planner.plan_fft(width, direction);
+    buffer
+        .par_chunks_mut(width)
+        .for_each(|row| row_fft.process(row));
+
+    let mut transposed = transpose(buffer, width, height);
+
+    let column_fft = planner.plan_fft(height, direction);
+    transposed
+        .par_chunks_mut(height)
+        .for_each(|column| column_fft.process(column));
+
+    buffer.copy_from_slice(&transpose(&transposed, height, width));
+}
+
+fn transpose(buffer: &[Complex32], width: usize, height: usize) -> Vec<Complex32> {
+    (0..width)
+        .flat_map(|x| (0..height).map(move |y| buffer[y * width + x]))
+        .collect()
+}
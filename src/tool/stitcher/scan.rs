@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::{CheckDirection, ImageStitcher, StitchEvent};
+
+/// Keeps track of the search for the offset between a pair of images, telling the observer how
+/// far along it is and whether it has to stop early.
+pub(super) struct Scan<'a> {
+    stitcher: &'a ImageStitcher,
+    pair: Option<(usize, usize)>,
+    direction: CheckDirection,
+    total: AtomicUsize,
+    scanned: AtomicUsize,
+    truncated: AtomicBool,
+}
+
+impl<'a> Scan<'a> {
+    /// Searches without a pair are not reported to the observer.
+    pub(super) fn new(
+        stitcher: &'a ImageStitcher,
+        pair: Option<(usize, usize)>,
+        direction: CheckDirection,
+    ) -> Self {
+        Self {
+            stitcher,
+            pair,
+            direction,
+            total: AtomicUsize::new(0),
+            scanned: AtomicUsize::new(0),
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    pub(super) fn pair(&self) -> Option<(usize, usize)> {
+        self.pair
+    }
+
+    pub(super) fn direction(&self) -> CheckDirection {
+        self.direction
+    }
+
+    /// Sets how many offsets are expected to be scanned, searches that narrow down as they go
+    /// only know an estimate up front.
+    pub(super) fn expect(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Counts `offsets` more as scanned, the observer is only told when another percent is done
+    /// so a big search does not flood it.
+    pub(super) fn advance(&self, offsets: usize) {
+        if self.pair.is_none() || self.stitcher.observer.is_none() {
+            return;
+        }
+
+        let scanned = self.scanned.fetch_add(offsets, Ordering::Relaxed);
+        let total = self.total.load(Ordering::Relaxed).max(1);
+        let percent = |scanned: usize| (scanned * 100 / total).min(100);
+
+        if percent(scanned + offsets) > percent(scanned) {
+            self.emit(percent(scanned + offsets) as f64 / 100.0);
+        }
+    }
+
+    /// Marks the scan as done, in case the search needed fewer offsets than expected.
+    pub(super) fn finish(&self) {
+        let total = self.total.load(Ordering::Relaxed).max(1);
+
+        if self.scanned.swap(total, Ordering::Relaxed) < total {
+            self.emit(1.0);
+        }
+    }
+
+    /// Whether the search has to stop, because the stitch was cancelled or ran out of time.
+    pub(super) fn stopped(&self) -> bool {
+        if self.stitcher.is_cancelled() {
+            return true;
+        }
+
+        let out_of_time = self
+            .stitcher
+            .time_budget
+            .is_some_and(|budget| self.stitcher.clock.elapsed() >= budget);
+
+        if out_of_time {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+
+        out_of_time
+    }
+
+    /// Whether the search stopped early because it ran out of time.
+    pub(super) fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    fn emit(&self, progress: f64) {
+        if let Some(((first, second), observer)) = self.pair.zip(self.stitcher.observer.as_ref()) {
+            observer.0.on_event(StitchEvent::PairProgress {
+                first,
+                second,
+                direction: self.direction,
+                progress,
+            });
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+
+use image::RgbaImage;
+
+use crate::{error::StitchError, util::time::Stopwatch};
+
+use super::{ImageStitcher, MatchConfidence, Position, stitcher::OrderedStitch};
+
+/// Stitches images one at a time as they come in, so the stitched image can be shown while
+/// more images are still being captured.
+///
+/// Every pushed image is matched against the one pushed before it, as it was drawn on the
+/// current image, the same way [`super::Order::Ordered`] does. [`super::StickyMode::Auto`] is not used, it needs all the
+/// images up front.
+pub struct StitchSession {
+    stitcher: ImageStitcher,
+    state: OrderedStitch,
+}
+
+impl StitchSession {
+    pub(super) fn new(stitcher: ImageStitcher) -> Self {
+        Self {
+            stitcher,
+            state: OrderedStitch::default(),
+        }
+    }
+
+    /// Stitches `image` onto the end of the current image, returning the confidence of the new
+    /// seam or `None` for the first image. The session is left as it was when this fails.
+    pub fn push(&mut self, image: RgbaImage) -> Result<Option<&MatchConfidence>, StitchError> {
+        let index = self.len();
+
+        self.stitcher.clock = Stopwatch::start();
+        self.stitcher.validate_image(index, &image)?;
+
+        let (stitcher, state) = (&self.stitcher, &mut self.state);
+
+        stitcher.threads.install(&stitcher.single_thread, || {
+            stitcher.stitch_ordered_next(state, image, stitcher.exclusions.for_image(index))
+        })??;
+
+        Ok(self.state.seams.last().filter(|_| index > 0))
+    }
+
+    /// The images stitched together so far, without the background and padding.
+    pub fn image(&self) -> Option<&RgbaImage> {
+        self.state.image.as_ref()
+    }
+
+    /// The stitch positions so far, in the same order [`ImageStitcher::stitch`] returns them.
+    pub fn positions(&self) -> &VecDeque<Position> {
+        &self.state.positions
+    }
+
+    /// The confidence of every seam so far, in the order the images were pushed.
+    pub fn seams(&self) -> &[MatchConfidence] {
+        &self.state.seams
+    }
+
+    /// Number of images stitched so far.
+    pub fn len(&self) -> usize {
+        match self.state.image {
+            Some(_) => self.state.positions.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.image.is_none()
+    }
+
+    /// Ends the session, returning the stitched image, the stitch positions and the confidence
+    /// of every seam, the same as the [`super::StitchReport`] of [`ImageStitcher::stitch`].
+    pub fn finish(
+        self,
+    ) -> Result<(RgbaImage, VecDeque<Position>, Vec<MatchConfidence>), StitchError> {
+        let len = self.len();
+
+        match self.state.image {
+            Some(image) if len >= 2 => Ok((
+                ImageStitcher::fill_canvas(image, &self.stitcher.composite),
+                self.state.positions,
+                self.state.seams,
+            )),
+            _ => Err(StitchError::TooFewImages(len)),
+        }
+    }
+}
@@ -1,22 +1,98 @@
-use std::{borrow::Cow, collections::VecDeque};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    sync::OnceLock,
+    time::Duration,
+};
 
-use image::{Pixel, RgbaImage, buffer::Pixels, imageops::rotate90};
+use image::{
+    GrayImage, Luma, Pixel, Rgba, RgbaImage,
+    imageops::{FilterType, crop_imm, replace, resize, rotate90},
+};
 use itertools::Itertools;
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::{
+    ThreadPool,
+    iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+};
+
+use crate::{
+    error::StitchError,
+    util::{
+        image::{ImageCrop, edge_detection},
+        time::Stopwatch,
+    },
+};
 
-use crate::util::{
-    image::{ImageCrop, edge_detection},
-    iter::IterWindows as _,
+use super::{
+    blend::{blend_overlap, blend_overlap_part, overlap_to_blend},
+    cancel::CancelToken,
+    observer::{Observer, StitchEvent},
+    params::{
+        Align, CheckDirection, CompositeOptions, DEFAULT_ALPHA_THRESHOLD, Exclusions, InputLayout,
+        LowConfidence, MatchConfidence, MatchMode, OffsetHint, OffsetHints, Order, OverlapScore,
+        Placement, Position, Rect, ScaleMode, SeamReport, SearchMode, StickyBands, StickyMode,
+        StitchReport, StitchTimings, Threads,
+    },
+    phase::phase_correlation_peaks,
+    scan::Scan,
 };
 
-use super::params::{CheckDirection, MatchMode, Order, OverlapScore, Position};
+/// Smallest side a pyramid level is allowed to have before the search stops downscaling.
+const PYRAMID_MIN_SIZE: u32 = 64;
+const PYRAMID_MAX_LEVELS: usize = 4;
+/// Number of best offsets carried over from one pyramid level to the next finer one.
+const PYRAMID_CANDIDATES: usize = 16;
+const PYRAMID_REFINE_RADIUS: i32 = 2;
+/// Number of phase correlation peaks that get scored, the best scoring one decides the offset. A
+/// peak can be strongest just because it lines up more of the images, not because they match.
+const PHASE_CORRELATION_PEAKS: usize = 8;
+/// Candidates closer than this to the best one are treated as the same match when rating how
+/// ambiguous it is.
+const AMBIGUITY_RADIUS: i32 = 8;
+/// Sticky headers and footers can each take up at most this fraction of an image, the rest is
+/// left for matching.
+const STICKY_MAX_FRACTION: u32 = 3;
+/// Mean difference per channel a row can have between the images and still count as sticky,
+/// leaves room for compression noise.
+const STICKY_TOLERANCE: u64 = 2;
+/// [`CheckDirection::Auto`] only tries [`CheckDirection::Sideways`] when neither the vertical nor
+/// the horizontal match reaches this confidence.
+const AUTO_SIDEWAYS_CONFIDENCE: f64 = 0.9;
+/// Scales [`ScaleMode::Auto`] tries on top of matching the breadth, the ratios between common
+/// device pixel ratios and browser zoom levels.
+const AUTO_SCALES: [f64; 8] = [0.5, 2.0, 2.0 / 3.0, 1.5, 0.75, 4.0 / 3.0, 0.8, 1.25];
+/// [`ScaleMode::Auto`] keeps the breadth match without trying other scales when it is at least
+/// this confident.
+const AUTO_SCALE_CONFIDENCE: f64 = 0.9;
+
 pub struct ImageStitcher {
-    images: Vec<RgbaImage>,
-    order: Order,
-    direction: CheckDirection,
-    window_size: usize,
-    match_mode: MatchMode,
-    crop: u32,
+    pub(super) images: Vec<RgbaImage>,
+    pub(super) order: Order,
+    pub(super) direction: CheckDirection,
+    pub(super) window_size: usize,
+    pub(super) match_mode: MatchMode,
+    pub(super) crop: u32,
+    pub(super) search_mode: SearchMode,
+    pub(super) composite: CompositeOptions,
+    pub(super) min_confidence: Option<f64>,
+    pub(super) low_confidence: LowConfidence,
+    pub(super) alpha_threshold: u8,
+    pub(super) exclusions: Exclusions,
+    pub(super) sticky: StickyMode,
+    pub(super) offset_hints: OffsetHints,
+    pub(super) grid_columns: Option<usize>,
+    pub(super) scale: ScaleMode,
+    pub(super) observer: Option<Observer>,
+    pub(super) cancel: Option<CancelToken>,
+    pub(super) time_budget: Option<Duration>,
+    /// When the current stitch started, the time budget counts from it.
+    pub(super) clock: Stopwatch,
+    pub(super) threads: Threads,
+    /// The thread of [`Threads::Single`], started the first time it is needed so a session runs
+    /// every pushed image on the same one.
+    pub(super) single_thread: OnceLock<ThreadPool>,
+    pub(super) deterministic: bool,
 }
 
 impl ImageStitcher {
@@ -35,510 +111,2484 @@ impl ImageStitcher {
             window_size,
             match_mode,
             crop,
+            search_mode: SearchMode::Pyramid,
+            composite: CompositeOptions::default(),
+            min_confidence: None,
+            low_confidence: LowConfidence::Fail,
+            alpha_threshold: DEFAULT_ALPHA_THRESHOLD,
+            exclusions: Exclusions::default(),
+            sticky: StickyMode::Off,
+            offset_hints: OffsetHints::default(),
+            grid_columns: None,
+            scale: ScaleMode::Off,
+            observer: None,
+            cancel: None,
+            time_budget: None,
+            clock: Stopwatch::start(),
+            threads: Threads::Global,
+            single_thread: OnceLock::new(),
+            deterministic: false,
         }
     }
 
-    pub fn stitch(self) -> (RgbaImage, VecDeque<Position>) {
-        let mut last_offset: Option<&Position> = None;
-        let mut final_image: Option<RgbaImage> = None;
-        let mut stitch_positions: VecDeque<Position> = VecDeque::new();
+    /// Stitches the images together, returning the final image and a report of where every
+    /// image ended up and how well every seam matched.
+    ///
+    /// [`Order::Unordered`] matches every pair of images once and stitches them in the sequence
+    /// whose neighbours match best, the positions, seams and placements follow that sequence.
+    pub fn stitch(mut self) -> Result<(RgbaImage, StitchReport), StitchError> {
+        let single_thread = std::mem::take(&mut self.single_thread);
+
+        self.threads
+            .clone()
+            .install(&single_thread, || self.stitch_on_threads())?
+    }
+
+    fn stitch_on_threads(mut self) -> Result<(RgbaImage, StitchReport), StitchError> {
+        self.clock = Stopwatch::start();
 
-        match self.order {
+        let matching = Stopwatch::start();
+        let scales = self.scale_images()?;
+
+        self.check_cancelled()?;
+
+        let sizes = self
+            .images
+            .iter()
+            .map(|image| image.dimensions())
+            .collect::<Vec<_>>();
+        let original_size = sizes.first().copied().unwrap_or_default();
+        let sticky_bands = self.sticky_bands();
+        let mut cut_bands = self.remove_sticky_bands(sticky_bands);
+
+        self.validate()?;
+
+        let images = std::mem::take(&mut self.images);
+
+        let (images, sequence, regions) = match self.order {
             Order::Ordered => {
-                for (image1, image2) in self.images.iter().tuple_windows::<(_, _)>() {
-                    let image1 = final_image.as_ref().unwrap_or_else(|| image1);
-
-                    let region = Self::find_stitch_region(
-                        image1,
-                        image2,
-                        self.direction,
-                        Order::Ordered,
-                        self.window_size,
-                        &self.match_mode,
-                        self.crop,
-                        last_offset,
-                    );
-
-                    let result = Self::stitch_images(
-                        image1,
-                        image2,
-                        &region,
-                        false,
-                        self.crop,
-                        self.direction,
-                    );
-
-                    Self::add_to_positions_ordered(
-                        &mut stitch_positions,
-                        region.position.clone(),
-                        false,
-                    );
-
-                    last_offset = Some(stitch_positions.iter().next().unwrap());
-                    final_image = Some(result);
+                let mut regions = Vec::with_capacity(images.len() - 1);
+                let matched = self.match_images(&images);
+
+                for (index, (image1, image2)) in matched.iter().tuple_windows().enumerate() {
+                    // Hints are about the images as they were given, before the bands were cut.
+                    let previous = match sticky_bands == StickyBands::default() {
+                        true => image1.image.dimensions(),
+                        false => original_size,
+                    };
+                    let hint = self
+                        .offset_hints
+                        .for_pair(index)
+                        .map(|hint| PairHint { hint, previous });
+
+                    let mut region =
+                        self.find_stitch_region((image1, image2), hint, Some((index, index + 1)))?;
+
+                    self.check_cancelled()?;
+                    self.check_confidence(&mut region, index)?;
+                    regions.push(region);
                 }
+
+                let sequence = (0..images.len()).collect();
+
+                (images, sequence, regions)
             }
             Order::Unordered => {
-                let mut images: VecDeque<RgbaImage> = self.images.into();
-                let mut stitch_positions_hash: VecDeque<(usize, Position)> = VecDeque::new();
-
-                while images.len() > 1 {
-                    let mut best_region = ([0, 1], OverlapScore::default());
-
-                    for (index, image1) in images.iter().enumerate() {
-                        for (index2, image2) in images.iter().enumerate().skip(index + 1) {
-                            let region = Self::find_stitch_region(
-                                image1,
-                                image2,
-                                self.direction,
-                                Order::Unordered,
-                                self.window_size,
-                                &self.match_mode,
-                                self.crop,
-                                None,
-                            );
-
-                            if region.score > best_region.1.score {
-                                best_region = ([index, index2], region);
-                            }
-                        }
-                    }
+                let (sequence, mut regions) = self.unordered_sequence(&images)?;
 
-                    let (flipped, start, end) = match best_region.0[0] > best_region.0[1] {
-                        true => (best_region.1.flipped, best_region.0[0], best_region.0[1]),
-                        false => (!best_region.1.flipped, best_region.0[1], best_region.0[0]),
-                    };
+                self.check_cancelled()?;
 
-                    let image1 = images.remove(start).unwrap();
-                    let image2 = images.remove(end).unwrap();
-                    let stitched_image = Self::stitch_images(
-                        &image1,
-                        &image2,
-                        &best_region.1,
-                        flipped,
-                        self.crop,
-                        self.direction,
-                    );
-
-                    let new_image_id = stitched_image.as_ptr() as usize;
-                    let image1_id = image1.as_ptr() as usize;
-                    let image2_id = image2.as_ptr() as usize;
-
-                    images.push_back(stitched_image);
-
-                    Self::add_to_positions_unordered(
-                        &mut stitch_positions_hash,
-                        best_region.1.position.clone(),
-                        flipped,
-                        new_image_id,
-                        (image1_id, image2_id),
-                    );
+                for (seam, region) in regions.iter_mut().enumerate() {
+                    self.check_confidence(region, seam)?;
                 }
 
-                final_image = Some(images.pop_back().unwrap());
-                stitch_positions = stitch_positions_hash.into_iter().map(|(_, p)| p).collect();
+                let mut images = images.into_iter().map(Some).collect::<Vec<_>>();
+                let images = sequence
+                    .iter()
+                    .map(|&index| images[index].take().expect("every image is used once"))
+                    .collect::<Vec<_>>();
+
+                (images, sequence, regions)
             }
-        }
+            Order::Grid => return self.stitch_grid(images, &sizes, &scales, matching),
+        };
 
-        (final_image.expect("should be set by now"), stitch_positions)
-    }
+        let matching = matching.elapsed();
+        let compositing = Stopwatch::start();
 
-    fn add_to_positions_ordered(
-        positions: &mut VecDeque<Position>,
-        new_position: Position,
-        flipped: bool,
-    ) {
-        if positions.len() == 0 {
-            return positions.push_front(new_position);
-        }
+        let (mut placements, positions) = self.layout_ordered(&images, &regions)?;
 
-        match flipped {
-            true => {
-                for pos in positions.iter_mut() {
-                    pos.x += new_position.x;
-                    pos.y += new_position.y;
-                }
+        self.notify(StitchEvent::CompositingStarted);
+        let final_image = Self::composite(&images, &placements, &self.composite)?;
 
-                positions.push_back(new_position);
-            }
-            false => {
-                positions.push_front(new_position);
-            }
+        for placement in placements.iter_mut() {
+            placement.index = sequence[placement.index];
         }
-    }
-
-    fn add_to_positions_unordered(
-        positions: &mut VecDeque<(usize, Position)>,
-        new_position: Position,
-        flipped: bool,
-        result_ptr: usize,
-        (id1, id2): (usize, usize),
-    ) {
-        let secondary_id = match flipped {
-            true => id1,
-            false => id2,
-        };
-
-        for (id, pos) in positions.iter_mut() {
-            if *id != id1 && *id != id2 {
-                continue;
-            }
 
-            if *id == secondary_id {
-                *pos += &new_position;
-            }
+        let seams = sequence
+            .iter()
+            .tuple_windows()
+            .zip(regions)
+            .map(|((&first, &second), region)| SeamReport {
+                first,
+                second,
+                score: region.score,
+                flipped: second < first,
+                confidence: region.confidence,
+            })
+            .collect();
+
+        // The bands are put back from the images the stitched sequence starts and ends with, which
+        // are not the first and last ones given when the images are unordered.
+        let ends = (sequence[0], sequence[sequence.len() - 1]);
+        let sticky_images = (
+            cut_bands
+                .get_mut(ends.0)
+                .and_then(|(header, _)| header.take()),
+            cut_bands
+                .get_mut(ends.1)
+                .and_then(|(_, footer)| footer.take()),
+        );
+
+        Self::add_sticky_placements(
+            &mut placements,
+            sticky_bands,
+            ends,
+            original_size,
+            final_image.dimensions(),
+            self.direction,
+        );
+
+        let inputs = self.input_layouts(&placements, &sizes, &scales);
+        let final_image = Self::fill_canvas(
+            Self::attach_sticky_bands(final_image, sticky_images, self.direction),
+            &self.composite,
+        );
+        self.notify(StitchEvent::CompositingFinished);
+
+        Ok((
+            final_image,
+            StitchReport {
+                inputs,
+                seams,
+                direction: self.direction,
+                crop: self.crop,
+                sticky_bands,
+                positions,
+                placements,
+                timings: StitchTimings {
+                    matching,
+                    compositing: compositing.elapsed(),
+                },
+            },
+        ))
+    }
 
-            *id = result_ptr;
+    /// Where every input image ended up, from the placements of the images in the order they
+    /// were stitched.
+    fn input_layouts(
+        &self,
+        placements: &[Placement],
+        sizes: &[(u32, u32)],
+        scales: &[f64],
+    ) -> Vec<InputLayout> {
+        // The sticky bands are added after the placement of every image in the sequence.
+        let mut inputs = vec![InputLayout::default(); sizes.len()];
+
+        for (order, placement) in placements.iter().take(sizes.len()).enumerate() {
+            let (width, height) = sizes[placement.index];
+
+            inputs[placement.index] = InputLayout {
+                sequence: order,
+                x: placement.x - placement.crop.left as i32 + self.composite.padding as i32,
+                y: placement.y - placement.crop.top as i32 + self.composite.padding as i32,
+                width,
+                height,
+                scale: scales[placement.index],
+            };
         }
 
-        positions.push_front((result_ptr, new_position));
+        inputs
     }
 
-    fn stack_images_with_overlap(
-        top_image: &RgbaImage,
-        bottom_image: &RgbaImage,
-        position: &Position,
-        flipped: bool,
-    ) -> RgbaImage {
-        let (top_image, bottom_image) = match flipped {
-            true => (bottom_image, top_image),
-            false => (top_image, bottom_image),
+    /// Resamples the images for [`ScaleMode::Breadth`] and [`ScaleMode::Auto`] along with their
+    /// exclusions, returning the factor every image was scaled by.
+    fn scale_images(&mut self) -> Result<Vec<f64>, StitchError> {
+        let mut scales = vec![1.0; self.images.len()];
+
+        let Some(first) = self.images.first() else {
+            return Ok(scales);
         };
 
-        let top_width = top_image.width();
-        let top_height = top_image.height();
-        let bottom_width = bottom_image.width();
-        let bottom_height = bottom_image.height();
+        if self.scale == ScaleMode::Off {
+            return Ok(scales);
+        }
 
-        let overlap_x_abs = position.x.unsigned_abs();
-        let overlap_y_abs = position.y.unsigned_abs();
+        // Runs before the images are validated, an empty image would scale the others by an
+        // infinite factor.
+        if let Some(index) = self
+            .images
+            .iter()
+            .position(|image| self.breadth(image) == 0)
+        {
+            return Err(StitchError::EmptyImage { index });
+        }
 
-        assert!(overlap_y_abs < (bottom_height + top_height));
-        assert!(overlap_x_abs < (bottom_width + top_width));
+        let breadth = self.breadth(first);
 
-        let output_width = top_width + bottom_width;
-        let output_height = top_height + bottom_height;
+        for index in 1..self.images.len() {
+            let fit = breadth as f64 / self.breadth(&self.images[index]) as f64;
 
-        let output_width = match position.x >= 0 {
-            true => output_width - (top_width - overlap_x_abs),
-            false => top_width + overlap_x_abs,
-        };
-        let output_height = match position.y >= 0 {
-            true => output_height - (top_height - overlap_y_abs),
-            false => top_height + overlap_y_abs,
+            scales[index] = match (self.scale, self.order) {
+                (ScaleMode::Auto, Order::Ordered) => self.estimate_scale(index, fit, &scales),
+                _ => fit,
+            };
+
+            if scales[index] != 1.0 {
+                self.images[index] = scale_image(&self.images[index], scales[index]);
+            }
+        }
+
+        self.exclusions = match &self.exclusions {
+            Exclusions::Shared(rects) if scales.iter().any(|&scale| scale != 1.0) => {
+                Exclusions::PerImage(
+                    scales
+                        .iter()
+                        .map(|&scale| scale_rects(rects, scale))
+                        .collect(),
+                )
+            }
+            Exclusions::Shared(rects) => Exclusions::Shared(rects.clone()),
+            // Keeps the number of groups so a mismatch is still reported.
+            Exclusions::PerImage(images) => Exclusions::PerImage(
+                images
+                    .iter()
+                    .enumerate()
+                    .map(|(index, rects)| {
+                        scale_rects(rects, scales.get(index).copied().unwrap_or(1.0))
+                    })
+                    .collect(),
+            ),
         };
 
-        let output_width = output_width.max(top_width);
-        let output_height = output_height.max(top_height);
+        Ok(scales)
+    }
 
-        let mut output_image = RgbaImage::new(output_width, output_height);
+    /// Picks the scale of image `index` that matches the already scaled image before it most
+    /// confidently, starting from `fit` which gives them the same breadth.
+    fn estimate_scale(&self, index: usize, fit: f64, scales: &[f64]) -> f64 {
+        let previous = &self.images[index - 1];
 
-        let copy_first_x = if position.x >= 0 { 0 } else { overlap_x_abs };
-        let copy_first_y = if position.y >= 0 { 0 } else { overlap_y_abs };
+        if self.validate_image(index - 1, previous).is_err() {
+            return fit;
+        }
 
-        for y in 0..top_height {
-            for x in 0..top_width {
-                let pixel = top_image.get_pixel(x, y);
-                output_image.put_pixel(x + copy_first_x, y + copy_first_y, *pixel);
+        let previous_exclusions =
+            scale_rects(self.exclusions.for_image(index - 1), scales[index - 1]);
+        let previous = MatchImage::new(previous, &previous_exclusions);
+        let confidence = |scale: f64| {
+            let image = scale_image(&self.images[index], scale);
+
+            if self.validate_image(index, &image).is_err() {
+                return None;
             }
-        }
 
-        let copy_second_x = if position.x >= 0 { overlap_x_abs } else { 0 };
-        let copy_second_y = if position.y >= 0 { overlap_y_abs } else { 0 };
+            let exclusions = scale_rects(self.exclusions.for_image(index), scale);
+            let region = self
+                .find_stitch_region(
+                    (&previous, &MatchImage::new(&image, &exclusions)),
+                    None,
+                    None,
+                )
+                .ok()?;
 
-        for y in 0..bottom_height {
-            for x in 0..bottom_width {
-                let output_x = copy_second_x + x;
-                let output_y = copy_second_y + y;
+            Some((scale, region.confidence.confidence))
+        };
 
-                if output_y < output_height && output_x < output_width {
-                    let pixel = bottom_image.get_pixel(x, y);
-                    output_image.put_pixel(output_x, output_y, *pixel);
-                }
-            }
+        let best = confidence(fit).unwrap_or((fit, f64::MIN));
+
+        if best.1 >= AUTO_SCALE_CONFIDENCE {
+            return fit;
         }
 
-        output_image
+        AUTO_SCALES
+            .par_iter()
+            .filter_map(|&relative| confidence(fit * relative))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold(best, |best, candidate| match candidate.1 > best.1 {
+                true => candidate,
+                false => best,
+            })
+            .0
     }
 
-    #[inline(always)]
-    fn pixel_as_value<P>(pixel: &P) -> u64
-    where
-        P: Pixel,
-        P::Subpixel: Into<u64>,
-    {
-        let mut channels = pixel.channels();
-        let mut num_of_channels = channels.len();
+    /// Side of `image` across the stitch direction, grid tiles use the width.
+    fn breadth(&self, image: &RgbaImage) -> u32 {
+        match (self.order, self.direction) {
+            (Order::Ordered | Order::Unordered, CheckDirection::Horizontal) => image.height(),
+            _ => image.width(),
+        }
+    }
 
-        if num_of_channels % 2 == 0 {
-            channels = &channels[..num_of_channels - 1];
-            num_of_channels -= 1;
+    /// Stitches the tiles of an [`Order::Grid`], every tile is placed from whichever of its left
+    /// and upper neighbours matched it more confidently.
+    ///
+    /// Tiles are drawn whole in the order they were given, with the crop cut off the sides
+    /// they share with the tiles drawn before them.
+    fn stitch_grid(
+        &self,
+        images: Vec<RgbaImage>,
+        sizes: &[(u32, u32)],
+        scales: &[f64],
+        matching: Stopwatch,
+    ) -> Result<(RgbaImage, StitchReport), StitchError> {
+        let count = images.len();
+        let candidates = match self.grid_columns {
+            Some(columns) => vec![columns],
+            None => (1..=count)
+                .filter(|&columns| count.is_multiple_of(columns))
+                .collect(),
+        };
+
+        let pairs = candidates
+            .iter()
+            .flat_map(|&columns| Self::grid_pairs(count, columns))
+            .unique()
+            .collect::<Vec<_>>();
+        let matched = self.match_images(&images);
+        let regions = pairs
+            .par_iter()
+            .map(|&(first, second, direction)| {
+                self.observe_pair(Some((first, second)), || {
+                    self.find_directed_stitch_region(
+                        (
+                            matched[first].input(self, direction),
+                            matched[second].input(self, direction),
+                        ),
+                        None,
+                        &Scan::new(self, Some((first, second)), direction),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.check_cancelled()?;
+        let mut matches = pairs.into_iter().zip(regions).collect::<HashMap<_, _>>();
+
+        // A wrong number of columns pairs up tiles that are not next to each other, which match
+        // worse than the real neighbours. Ties go to fewer columns.
+        let mean_confidence = |columns: usize| {
+            let (sum, pairs) = Self::grid_pairs(count, columns)
+                .fold((0.0, 0), |(sum, pairs), pair| {
+                    (sum + matches[&pair].confidence.confidence, pairs + 1)
+                });
+            sum / pairs as f64
+        };
+        let columns = candidates
+            .into_iter()
+            .map(|columns| (columns, mean_confidence(columns)))
+            .max_by(|(columns, confidence), (other_columns, other_confidence)| {
+                confidence
+                    .total_cmp(other_confidence)
+                    .then(other_columns.cmp(columns))
+            })
+            .map(|(columns, _)| columns)
+            .expect("there is always at least one candidate");
+
+        let mut areas: Vec<Area> = vec![(0, 0, images[0].width(), images[0].height())];
+        let mut seams = Vec::with_capacity(count - 1);
+
+        for (index, image) in images.iter().enumerate().skip(1) {
+            let left =
+                (index % columns > 0).then(|| (index - 1, index, CheckDirection::Horizontal));
+            let above =
+                (index >= columns).then(|| (index - columns, index, CheckDirection::Vertical));
+            let pair = left
+                .into_iter()
+                .chain(above)
+                .max_by(|pair, other| {
+                    matches[pair]
+                        .confidence
+                        .confidence
+                        .total_cmp(&matches[other].confidence.confidence)
+                })
+                .expect("every tile after the first has a neighbour");
+
+            let mut region = matches.remove(&pair).expect("every neighbour was matched");
+            self.check_confidence(&mut region, seams.len())?;
+
+            let neighbour = areas[pair.0];
+            let (x, y) = self.pair_offset(&images[pair.0], image, &region);
+            areas.push((
+                neighbour.0 + x,
+                neighbour.1 + y,
+                image.width(),
+                image.height(),
+            ));
+
+            seams.push(SeamReport {
+                first: pair.0,
+                second: index,
+                score: region.score,
+                flipped: false,
+                confidence: region.confidence,
+            });
         }
 
-        channels
+        let matching = matching.elapsed();
+        let compositing = Stopwatch::start();
+
+        let drawn = areas
             .iter()
-            .map(|p| <<P as Pixel>::Subpixel as Into<u64>>::into(*p))
-            .sum::<u64>()
-            / (num_of_channels as u64)
-    }
+            .enumerate()
+            .map(|(index, &area)| {
+                let crop = ImageCrop {
+                    left: if index % columns > 0 { self.crop } else { 0 },
+                    top: if index >= columns { self.crop } else { 0 },
+                    ..Default::default()
+                };
 
-    #[inline(always)]
-    fn row_set_diff_score<'a, I1, I2, P>(
-        first_set: I1,
-        second_set: I2,
-        padding: i32,
-        window_size: u64,
-    ) -> u64
-    where
-        P: Pixel + 'a,
-        P::Subpixel: Into<u64>,
-        I1: Iterator<Item = Pixels<'a, P>>,
-        I2: Iterator<Item = Pixels<'a, P>>,
-    {
-        let mut sum = 0;
+                (crop_area(area, &crop), crop)
+            })
+            .collect::<Vec<_>>();
 
-        for (row1, row2) in first_set.into_iter().zip(second_set) {
-            sum += Self::row_diff_score(row1, row2, padding);
+        let mut positions = VecDeque::with_capacity(count - 1);
+        let mut canvas = drawn[0].0;
+
+        for &(area, _) in &drawn[1..] {
+            let position = Position {
+                x: (area.0 - canvas.0) as i32,
+                y: (area.1 - canvas.1) as i32,
+            };
+
+            Self::add_to_positions_ordered(&mut positions, position, false);
+            canvas = bounding_area(canvas, area);
         }
 
-        (u64::MAX - sum) / window_size
+        let placements = drawn
+            .into_iter()
+            .enumerate()
+            .map(|(index, (area, crop))| Placement {
+                index,
+                x: (area.0 - canvas.0) as i32,
+                y: (area.1 - canvas.1) as i32,
+                crop,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        self.notify(StitchEvent::CompositingStarted);
+        let final_image = Self::fill_canvas(
+            Self::composite(&images, &placements, &self.composite)?,
+            &self.composite,
+        );
+        self.notify(StitchEvent::CompositingFinished);
+
+        Ok((
+            final_image,
+            StitchReport {
+                inputs: self.input_layouts(&placements, sizes, scales),
+                seams,
+                direction: self.direction,
+                crop: self.crop,
+                sticky_bands: StickyBands::default(),
+                positions,
+                placements,
+                timings: StitchTimings {
+                    matching,
+                    compositing: compositing.elapsed(),
+                },
+            },
+        ))
+    }
+
+    /// Every tile paired with the one to its right and the one below it in a grid of `columns`.
+    fn grid_pairs(
+        count: usize,
+        columns: usize,
+    ) -> impl Iterator<Item = (usize, usize, CheckDirection)> {
+        (0..count).flat_map(move |index| {
+            let right = (index % columns + 1 < columns)
+                .then(|| (index, index + 1, CheckDirection::Horizontal));
+            let below = (index + columns < count)
+                .then(|| (index, index + columns, CheckDirection::Vertical));
+
+            right.into_iter().chain(below)
+        })
     }
 
-    #[inline(always)]
-    fn row_diff_score<P>(row1: Pixels<'_, P>, row2: Pixels<'_, P>, padding: i32) -> u64
-    where
-        P: Pixel,
-        P::Subpixel: Into<u64>,
-    {
-        let mut score = 0;
-        let first_iter = row1.map(Self::pixel_as_value);
-        let second_iter = row2.map(Self::pixel_as_value);
+    /// Offset of the top left corner of `second` from the one of `first` when it is stitched
+    /// onto it at `region`.
+    fn pair_offset(
+        &self,
+        first: &RgbaImage,
+        second: &RgbaImage,
+        region: &OverlapScore,
+    ) -> (i64, i64) {
+        let canvas_crop =
+            Self::stitch_crop(&region.position, self.crop, region.confidence.direction);
+        let (crop_x, crop_y, _, _) = canvas_crop
+            .clone()
+            .reverse()
+            .region(second.width(), second.height());
+        let first = crop_area((0, 0, first.width(), first.height()), &canvas_crop);
+        let position =
+            Self::stitch_position(&region.position, self.crop, region.confidence.direction);
+
+        (
+            first.0 + position.x as i64 - crop_x as i64,
+            first.1 + position.y as i64 - crop_y as i64,
+        )
+    }
 
-        let (first_to_match, first_collection) = match padding {
-            ..0 => {
-                let padding = (padding * -1) as usize;
+    /// Moves the placements of the images drawn in the stitched images to match the original
+    /// images once the sticky bands are cut off, and adds the bands themselves. The header comes
+    /// from `first` and the footer from `last`, the images the stitched sequence starts and ends
+    /// with.
+    fn add_sticky_placements(
+        placements: &mut Vec<Placement>,
+        bands: StickyBands,
+        (first, last): (usize, usize),
+        (width, height): (u32, u32),
+        (body_width, body_height): (u32, u32),
+        direction: CheckDirection,
+    ) {
+        if bands == StickyBands::default() {
+            return;
+        }
+
+        let (header, footer) = match direction {
+            CheckDirection::Horizontal => {
+                for placement in placements.iter_mut() {
+                    placement.x += bands.header as i32;
+                    placement.crop.left += bands.header;
+                    placement.crop.right += bands.footer;
+                }
 
                 (
-                    itertools::Either::Left(first_iter.clone().rev().take(padding)),
-                    itertools::Either::Right(first_iter),
+                    Placement {
+                        index: first,
+                        crop: ImageCrop {
+                            right: width - bands.header,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    Placement {
+                        index: last,
+                        x: (bands.header + body_width) as i32,
+                        crop: ImageCrop {
+                            left: width - bands.footer,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
                 )
             }
-            _ => (
-                itertools::Either::Right(first_iter.clone().take(padding as usize)),
-                itertools::Either::Left(first_iter.skip(padding as usize)),
-            ),
-        };
-
-        let (second_to_match, second_collection) = match padding {
-            ..0 => {
-                let padding = (padding * -1) as usize;
+            _ => {
+                for placement in placements.iter_mut() {
+                    placement.y += bands.header as i32;
+                    placement.crop.top += bands.header;
+                    placement.crop.bottom += bands.footer;
+                }
 
                 (
-                    itertools::Either::Right(second_iter.clone().take(padding)),
-                    itertools::Either::Left(second_iter.skip(padding)),
+                    Placement {
+                        index: first,
+                        crop: ImageCrop {
+                            bottom: height - bands.header,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    Placement {
+                        index: last,
+                        y: (bands.header + body_height) as i32,
+                        crop: ImageCrop {
+                            top: height - bands.footer,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
                 )
             }
-            _ => (
-                itertools::Either::Left(second_iter.clone().rev().take(padding as usize)),
-                itertools::Either::Right(second_iter),
-            ),
         };
 
-        score += first_to_match
-            .zip(second_to_match)
-            .map(|(p1, p2)| p1 + p2)
-            .sum::<u64>();
-
-        score += first_collection
-            .zip(second_collection)
-            .map(|(p1, p2)| p1.abs_diff(p2))
-            .sum::<u64>();
+        if bands.header > 0 {
+            placements.push(header);
+        }
 
-        score
+        if bands.footer > 0 {
+            placements.push(footer);
+        }
     }
 
-    fn find_stitch_region(
-        part1: &RgbaImage,
-        part2: &RgbaImage,
-        direction: CheckDirection,
-        order: Order,
-        window_size: usize,
-        match_mode: &MatchMode,
-        crop: u32,
-        skip: Option<&Position>,
-    ) -> OverlapScore {
-        use CheckDirection as CD;
-
-        let (part1_check, part2_check) = match direction {
-            CD::Vertical | CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft => (part1, part2),
-            CD::Horizontal => (&rotate90(part1), &rotate90(part2)),
+    /// Finds the rows at the start and end of the images that are the same in all of them.
+    ///
+    /// Only done for [`StickyMode::Auto`] with [`CheckDirection::Vertical`] or
+    /// [`CheckDirection::Horizontal`] and images of the same size, outside of [`Order::Grid`].
+    fn sticky_bands(&self) -> StickyBands {
+        let Some(first) = self.images.first() else {
+            return StickyBands::default();
         };
 
-        let (part1_check, part2_check) = match match_mode {
-            MatchMode::Normal => (Cow::Borrowed(part1_check), Cow::Borrowed(part2_check)),
-            MatchMode::Edges => (
-                Cow::Owned(edge_detection(part1_check)),
-                Cow::Owned(edge_detection(part2_check)),
-            ),
+        if self.sticky != StickyMode::Auto
+            || self.order == Order::Grid
+            || self.images.len() < 2
+            || self
+                .images
+                .iter()
+                .any(|image| image.dimensions() != first.dimensions())
+        {
+            return StickyBands::default();
+        }
+
+        let lines = match self.direction {
+            CheckDirection::Vertical => first.height(),
+            CheckDirection::Horizontal => first.width(),
+            CheckDirection::Sideways
+            | CheckDirection::SidewaysRight
+            | CheckDirection::SidewaysLeft
+            | CheckDirection::Auto => return StickyBands::default(),
         };
+        let limit = lines / STICKY_MAX_FRACTION;
 
-        let (_skip_x, skip_y) = match skip {
-            Some(skip) => match direction {
-                CD::Horizontal => (i32::MIN, skip.x as usize),
-                CD::Vertical | CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft => {
-                    (skip.x, skip.y as usize)
-                }
-            },
-            None => (i32::MIN, 0),
+        let sticky = |line: u32| {
+            self.images[1..]
+                .iter()
+                .all(|image| Self::lines_match(first, image, line, self.direction))
         };
 
-        let (horizontal_start, horizontal_move_end) = match direction {
-            CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft => {
-                let width = part2_check.width().max(part1_check.width());
-                let start = (width - 1 - crop) as i32 * -1;
-                let end = (width - crop) as i32;
+        StickyBands {
+            header: (0..limit).take_while(|&line| sticky(line)).count() as u32,
+            footer: (0..limit)
+                .take_while(|&line| sticky(lines - 1 - line))
+                .count() as u32,
+        }
+    }
 
-                (
-                    match direction {
-                        CD::Sideways | CD::SidewaysLeft => start,
-                        CD::SidewaysRight => 0,
-                        _ => unreachable!(),
-                    },
-                    match direction {
-                        CD::SidewaysRight | CD::Sideways => end,
-                        CD::SidewaysLeft => 0,
-                        _ => unreachable!(),
-                    },
-                )
-            }
-            _ => (0, 0),
+    /// Whether row `line` along `direction` is close enough to the same in both images.
+    fn lines_match(
+        first: &RgbaImage,
+        second: &RgbaImage,
+        line: u32,
+        direction: CheckDirection,
+    ) -> bool {
+        let breadth = match direction {
+            CheckDirection::Horizontal => first.height(),
+            _ => first.width(),
         };
 
-        let mut best_rows_to_merge = (vec![0 as isize; window_size], OverlapScore::default());
-
-        // TODO:
-        // 1. Figure out also make the vertical movement "start from negative"
-
-        let second_start: Vec<_> = part2_check
-            .rows()
-            .skip(crop as usize)
-            .windows(window_size)
-            .next()
-            .unwrap()
-            .into();
-
-        for horizontal_offset in horizontal_start..=horizontal_move_end {
-            let min = part1_check
-                .rows()
-                .take((part1_check.height() - crop) as usize)
-                .enumerate()
-                .skip(skip_y)
-                .map(|p| (p.0 as isize, p.1))
-                .windows(window_size)
-                .par_bridge()
-                .map(|row| {
-                    let (indices, row): (Vec<_>, Vec<_>) = row.into_iter().unzip();
-                    let row = row.into_iter();
-                    let row = match indices[0] < 0 {
-                        true => itertools::Either::Left(row.rev()),
-                        false => itertools::Either::Right(row),
-                    };
-
-                    let score = Self::row_set_diff_score(
-                        row,
-                        second_start.clone().into_iter(),
-                        horizontal_offset,
-                        window_size as u64,
-                    );
-
-                    let position = match direction {
-                        CD::Vertical | CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft => {
-                            Position {
-                                y: indices[0] as i32,
-                                x: horizontal_offset,
-                            }
-                        }
-                        CD::Horizontal => Position {
-                            x: indices[0] as i32,
-                            y: 0,
-                        },
-                    };
+        let difference: u64 = (0..breadth)
+            .map(|along| {
+                let (x, y) = match direction {
+                    CheckDirection::Horizontal => (line, along),
+                    _ => (along, line),
+                };
 
-                    (
-                        indices,
-                        OverlapScore {
-                            score,
-                            position,
-                            flipped: false,
-                        },
-                    )
-                })
-                .max_by_key(|i| i.1.score)
-                .unwrap_or_else(|| {
-                    (
-                        vec![0; window_size],
-                        OverlapScore {
-                            score: u64::MAX,
-                            position: Position::default(),
-                            flipped: false,
-                        },
-                    )
-                });
+                first
+                    .get_pixel(x, y)
+                    .channels()
+                    .iter()
+                    .zip(second.get_pixel(x, y).channels())
+                    .map(|(first, second)| first.abs_diff(*second) as u64)
+                    .sum::<u64>()
+            })
+            .sum();
+
+        difference <= STICKY_TOLERANCE * breadth as u64 * 4
+    }
 
-            if best_rows_to_merge.1.score < min.1.score {
-                best_rows_to_merge = min;
-            }
+    /// Cuts the sticky bands off every image and moves the exclusions along, returning the
+    /// header and footer of every image. Nothing is returned when there are no bands.
+    fn remove_sticky_bands(
+        &mut self,
+        bands: StickyBands,
+    ) -> Vec<(Option<RgbaImage>, Option<RgbaImage>)> {
+        if bands == StickyBands::default() {
+            return Vec::new();
         }
 
-        match order {
-            Order::Ordered => best_rows_to_merge.1,
-            Order::Unordered => {
-                let mut overlap2 = Self::find_stitch_region(
-                    part2,
-                    part1,
-                    direction,
-                    Order::Ordered,
-                    window_size,
-                    match_mode,
-                    crop,
-                    skip,
-                );
+        let (width, height) = self.images[0].dimensions();
+        let (header, kept, footer) = match self.direction {
+            CheckDirection::Horizontal => (
+                Rect::new(0, 0, bands.header, height),
+                Rect::new(bands.header, 0, width - bands.header - bands.footer, height),
+                Rect::new(width - bands.footer, 0, bands.footer, height),
+            ),
+            _ => (
+                Rect::new(0, 0, width, bands.header),
+                Rect::new(0, bands.header, width, height - bands.header - bands.footer),
+                Rect::new(0, height - bands.footer, width, bands.footer),
+            ),
+        };
 
-                overlap2.flipped = true;
+        let cut = |image: &RgbaImage, rect: Rect| {
+            crop_imm(image, rect.x, rect.y, rect.width, rect.height).to_image()
+        };
+        let band = |image: &RgbaImage, rect: Rect| {
+            (rect.width > 0 && rect.height > 0).then(|| cut(image, rect))
+        };
 
-                if best_rows_to_merge.1.score > overlap2.score {
-                    best_rows_to_merge.1
-                } else {
-                    overlap2
-                }
-            }
+        let cut_bands = self
+            .images
+            .iter()
+            .map(|image| (band(image, header), band(image, footer)))
+            .collect();
+
+        for image in self.images.iter_mut() {
+            *image = cut(image, kept);
         }
-    }
 
-    pub fn stitch_images(
-        part1: &RgbaImage,
-        part2: &RgbaImage,
-        position: &Position,
+        let move_rects = |rects: &Vec<Rect>| {
+            rects
+                .iter()
+                .filter_map(|rect| rect.intersection(&kept))
+                .map(|rect| Rect::new(rect.x - kept.x, rect.y - kept.y, rect.width, rect.height))
+                .collect()
+        };
+
+        self.exclusions = match &self.exclusions {
+            Exclusions::Shared(rects) => Exclusions::Shared(move_rects(rects)),
+            Exclusions::PerImage(images) => {
+                Exclusions::PerImage(images.iter().map(move_rects).collect())
+            }
+        };
+
+        cut_bands
+    }
+
+    /// Puts the sticky bands cut off by [`Self::remove_sticky_bands`] back at the start and end
+    /// of the stitched image.
+    fn attach_sticky_bands(
+        image: RgbaImage,
+        (header, footer): (Option<RgbaImage>, Option<RgbaImage>),
+        direction: CheckDirection,
+    ) -> RgbaImage {
+        if header.is_none() && footer.is_none() {
+            return image;
+        }
+
+        let size =
+            |band: &Option<RgbaImage>| band.as_ref().map_or((0, 0), |band| band.dimensions());
+        let (header_width, header_height) = size(&header);
+        let (footer_width, footer_height) = size(&footer);
+
+        let (width, height, image_at, footer_at) = match direction {
+            CheckDirection::Horizontal => (
+                header_width + image.width() + footer_width,
+                image.height().max(header_height).max(footer_height),
+                (header_width, 0),
+                (header_width + image.width(), 0),
+            ),
+            _ => (
+                image.width().max(header_width).max(footer_width),
+                header_height + image.height() + footer_height,
+                (0, header_height),
+                (0, header_height + image.height()),
+            ),
+        };
+
+        let mut result = RgbaImage::new(width, height);
+
+        if let Some(header) = &header {
+            replace(&mut result, header, 0, 0);
+        }
+
+        replace(&mut result, &image, image_at.0 as i64, image_at.1 as i64);
+
+        if let Some(footer) = &footer {
+            replace(&mut result, footer, footer_at.0 as i64, footer_at.1 as i64);
+        }
+
+        result
+    }
+
+    /// Stitches `image` onto the end of what a [`super::StitchSession`] has put together so far,
+    /// leaving `state` untouched when it fails.
+    pub(super) fn stitch_ordered_next(
+        &self,
+        state: &mut OrderedStitch,
+        image: RgbaImage,
+        exclusions: &[Rect],
+    ) -> Result<(), StitchError> {
+        let Some(final_image) = &state.image else {
+            state.last = (0, 0, image.width(), image.height());
+            state.image = Some(image);
+            state.exclusions = exclusions.to_vec();
+            return Ok(());
+        };
+
+        // Only the last image is matched against, so a session does not slow down as the
+        // stitched image grows.
+        let (last_x, last_y, last_width, last_height) = state.last;
+        let last = Rect::new(last_x as u32, last_y as u32, last_width, last_height);
+        let last_exclusions = state
+            .exclusions
+            .iter()
+            .filter_map(|rect| rect.intersection(&last))
+            .map(|rect| Rect::new(rect.x - last.x, rect.y - last.y, rect.width, rect.height))
+            .collect::<Vec<_>>();
+        let hint = self
+            .offset_hints
+            .for_pair(state.seams.len())
+            .map(|hint| PairHint {
+                hint,
+                previous: (last.width, last.height),
+            });
+
+        let last_image = crop_imm(final_image, last.x, last.y, last.width, last.height).to_image();
+        let mut region = self.find_stitch_region(
+            (
+                &MatchImage::new(&last_image, &last_exclusions),
+                &MatchImage::new(&image, exclusions),
+            ),
+            hint,
+            Some((state.seams.len(), state.seams.len() + 1)),
+        )?;
+
+        self.check_cancelled()?;
+        self.check_confidence(&mut region, state.seams.len())?;
+
+        let direction = region.confidence.direction;
+        let mut canvas_crop = Self::stitch_crop(&region.position, self.crop, direction);
+        let crop = canvas_crop.clone().reverse();
+        let (crop_x, crop_y, width, height) = crop.region(image.width(), image.height());
+        let canvas: Area = (0, 0, final_image.width(), final_image.height());
+        let first = crop_area(canvas, &canvas_crop);
+        let position = Self::stitch_position(&region.position, self.crop, direction);
+        let second: Area = (
+            first.0 + last_x + position.x as i64,
+            first.1 + last_y + position.y as i64,
+            width,
+            height,
+        );
+
+        // The next image is matched against this one as it is drawn on the stitched image, so it
+        // has to stay where its content lines up.
+        let second = self.arrange(
+            (state.last, canvas),
+            &mut canvas_crop,
+            second,
+            direction,
+            Align::Content,
+        );
+        let first = crop_area(canvas, &canvas_crop);
+        let position = Position {
+            x: (second.0 - first.0) as i32,
+            y: (second.1 - first.1) as i32,
+        };
+
+        self.notify(StitchEvent::CompositingStarted);
+        let result = Self::stack_images_with_overlap(
+            &canvas_crop.crop_image(final_image),
+            &crop.crop_image(&image),
+            &position,
+            false,
+            &self.composite,
+        )?;
+        self.notify(StitchEvent::CompositingFinished);
+
+        state.exclusions = Self::stitch_exclusions(
+            (final_image, &image),
+            (&state.exclusions, exclusions),
+            &position,
+            false,
+            self.crop,
+            direction,
+        );
+        state.seams.push(region.confidence.clone());
+
+        let canvas = bounding_area(first, second);
+
+        state.last = (
+            second.0 - crop_x as i64 - canvas.0,
+            second.1 - crop_y as i64 - canvas.1,
+            image.width(),
+            image.height(),
+        );
+
+        Self::add_to_positions_ordered(&mut state.positions, position, false);
+
+        state.image = Some(result);
+
+        Ok(())
+    }
+
+    /// Matches every image against every other one, then picks the sequence of images whose
+    /// neighbours match best overall. Returns the sequence along with the regions
+    /// between neighbouring images in it.
+    ///
+    /// The sequence is built greedily from the best matches down, joining two images whenever
+    /// the first does not have an image after it yet, the second does not have one before it and
+    /// they are not already part of the same run of images.
+    fn unordered_sequence(
+        &self,
+        images: &[RgbaImage],
+    ) -> Result<(Vec<usize>, Vec<OverlapScore>), StitchError> {
+        let count = images.len();
+        let matched = self.match_images(images);
+        let matches = (0..count)
+            .tuple_combinations()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(first, second)| {
+                let pair = Some((first, second));
+                let region =
+                    self.find_stitch_region((&matched[first], &matched[second]), None, pair)?;
+
+                Ok(((first, second), region))
+            })
+            .collect::<Result<Vec<_>, StitchError>>()?;
+
+        // The second image is searched for on both sides of the first, so matching the other
+        // way round would only find the same offset turned around.
+        let (pairs, regions): (Vec<_>, Vec<_>) = matches
+            .into_iter()
+            .flat_map(|((first, second), region)| {
+                let reversed = self.reverse_region(&region);
+
+                [((first, second), region), ((second, first), reversed)]
+            })
+            .unzip();
+
+        // Pairs where the second image comes after the first go first so the runs of images
+        // all point the same way and can be joined, every pair is there both ways round so
+        // each pair has one. Every part two images share matches equally well, so ties go to the
+        // pair that overlaps the most to join neighbouring images before ones further apart.
+        let mut by_score = (0..pairs.len()).collect::<Vec<_>>();
+        by_score.sort_by_cached_key(|&pair| {
+            let (first, second) = pairs[pair];
+            let region = &regions[pair];
+            let forward = match region.confidence.direction {
+                CheckDirection::Horizontal => region.position.x >= 0,
+                CheckDirection::Vertical
+                | CheckDirection::Sideways
+                | CheckDirection::SidewaysRight
+                | CheckDirection::SidewaysLeft
+                | CheckDirection::Auto => region.position.y >= 0,
+            };
+
+            Reverse((
+                forward,
+                region.score,
+                self.overlap_size(&images[first], &images[second], region),
+            ))
+        });
+
+        let mut regions = regions.into_iter().map(Some).collect::<Vec<_>>();
+
+        let mut next = vec![None; count];
+        let mut previous = vec![None; count];
+        let mut runs = (0..count).collect::<Vec<_>>();
+
+        for pair in by_score {
+            let (first, second) = pairs[pair];
+
+            if next[first].is_some() || previous[second].is_some() || runs[first] == runs[second] {
+                continue;
+            }
+
+            next[first] = Some(pair);
+            previous[second] = Some(first);
+
+            let (joined, into) = (runs[second], runs[first]);
+            runs.iter_mut()
+                .filter(|run| **run == joined)
+                .for_each(|run| *run = into);
+        }
+
+        // Every pair is there both ways round, so the runs always end up joined into one.
+        let start = (0..count)
+            .find(|&index| previous[index].is_none())
+            .expect("the sequence has a start");
+        let mut sequence = vec![start];
+        let mut sequence_regions = Vec::with_capacity(count - 1);
+
+        while let Some(pair) = next[*sequence.last().expect("never empty")] {
+            sequence.push(pairs[pair].1);
+            sequence_regions.push(regions[pair].take().expect("every pair is used once"));
+        }
+
+        Ok((sequence, sequence_regions))
+    }
+
+    /// Prepares `images` to be matched, along with the exclusions of each.
+    fn match_images<'a>(&'a self, images: &'a [RgbaImage]) -> Vec<MatchImage<'a>> {
+        images
+            .iter()
+            .enumerate()
+            .map(|(index, image)| MatchImage::new(image, self.exclusions.for_image(index)))
+            .collect()
+    }
+
+    /// Where `first` goes on `second` from where `second` goes on `first`. Offsets are where
+    /// row `crop` of the second image lands, so turning one around moves it by both crops.
+    fn reverse_region(&self, region: &OverlapScore) -> OverlapScore {
+        use CheckDirection as CD;
+
+        let back = 2 * self.crop as i32;
+
+        OverlapScore {
+            score: region.score,
+            flipped: region.flipped,
+            position: match region.confidence.direction {
+                CD::Horizontal => Position {
+                    x: back - region.position.x,
+                    y: 0,
+                },
+                CD::Vertical | CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft | CD::Auto => {
+                    Position {
+                        x: -region.position.x,
+                        y: back - region.position.y,
+                    }
+                }
+            },
+            confidence: region.confidence.clone(),
+        }
+    }
+
+    /// Number of pixels two images share when `second` is stitched onto `first` at `region`.
+    fn overlap_size(&self, first: &RgbaImage, second: &RgbaImage, region: &OverlapScore) -> u64 {
+        let canvas_crop =
+            Self::stitch_crop(&region.position, self.crop, region.confidence.direction);
+        let (_, _, width, height) = canvas_crop
+            .clone()
+            .reverse()
+            .region(second.width(), second.height());
+
+        let first = crop_area((0, 0, first.width(), first.height()), &canvas_crop);
+        let position =
+            Self::stitch_position(&region.position, self.crop, region.confidence.direction);
+        let second: Area = (
+            first.0 + position.x as i64,
+            first.1 + position.y as i64,
+            width,
+            height,
+        );
+
+        let shared = |start: i64, length: u32, other_start: i64, other_length: u32| {
+            let end = (start + length as i64).min(other_start + other_length as i64);
+            (end - start.max(other_start)).max(0) as u64
+        };
+
+        shared(first.0, first.2, second.0, second.2) * shared(first.1, first.3, second.1, second.3)
+    }
+
+    /// Places every image from the offsets between neighbouring images, the same way stitching
+    /// each image onto the ones before it would.
+    fn layout_ordered(
+        &self,
+        images: &[RgbaImage],
+        regions: &[OverlapScore],
+    ) -> Result<(Vec<Placement>, VecDeque<Position>), StitchError> {
+        let mut origin = (0, 0);
+        let mut canvas: Area = (0, 0, images[0].width(), images[0].height());
+        let mut placements = vec![Placement::default()];
+        let mut areas = vec![canvas];
+        let mut positions = VecDeque::new();
+
+        for (index, (image, region)) in images[1..].iter().zip(regions).enumerate() {
+            // Offsets are found against the previous image, the positions are against
+            // everything stitched before it, same as matching against the stitched image.
+            let position = Position {
+                x: (origin.0 + region.position.x as i64 - canvas.0) as i32,
+                y: (origin.1 + region.position.y as i64 - canvas.1) as i32,
+            };
+
+            let mut canvas_crop =
+                Self::stitch_crop(&position, self.crop, region.confidence.direction);
+            let crop = canvas_crop.clone().reverse();
+            let (crop_x, crop_y, width, height) = crop.region(image.width(), image.height());
+
+            let first = crop_area(canvas, &canvas_crop);
+            let position = Self::stitch_position(&position, self.crop, region.confidence.direction);
+            let second: Area = (
+                first.0 + position.x as i64,
+                first.1 + position.y as i64,
+                width,
+                height,
+            );
+
+            Self::check_overlap(&position, (first.2, first.3), (second.2, second.3))?;
+
+            let previous = *areas.last().expect("the first image is always there");
+            let second = self.arrange(
+                (previous, canvas),
+                &mut canvas_crop,
+                second,
+                region.confidence.direction,
+                self.composite.align,
+            );
+            let first = crop_area(canvas, &canvas_crop);
+            let position = Position {
+                x: (second.0 - first.0) as i32,
+                y: (second.1 - first.1) as i32,
+            };
+
+            Self::add_to_positions_ordered(&mut positions, position, false);
+
+            placements.push(Placement {
+                index: index + 1,
+                crop,
+                canvas_crop,
+                ..Default::default()
+            });
+            areas.push(second);
+
+            origin = (second.0 - crop_x as i64, second.1 - crop_y as i64);
+            canvas = bounding_area(first, second);
+        }
+
+        for (placement, area) in placements.iter_mut().zip(areas) {
+            placement.x = (area.0 - canvas.0) as i32;
+            placement.y = (area.1 - canvas.1) as i32;
+        }
+
+        Ok((placements, positions))
+    }
+
+    /// Moves the drawn part of the next image across the stitch direction to follow `align` and
+    /// along it by [`CompositeOptions::gap`], cutting the canvas off where the image starts so
+    /// the gap stays empty.
+    ///
+    /// `previous` is where the image before it sits, `canvas` is everything drawn so far before
+    /// `canvas_crop` is applied.
+    fn arrange(
+        &self,
+        (previous, canvas): (Area, Area),
+        canvas_crop: &mut ImageCrop,
+        second: Area,
+        direction: CheckDirection,
+        align: Align,
+    ) -> Area {
+        use CheckDirection as CD;
+
+        let vertical = direction != CD::Horizontal;
+        // (start, length) along the stitch direction and across it.
+        let along = |area: Area| match vertical {
+            true => (area.1, area.3),
+            false => (area.0, area.2),
+        };
+        let across = |area: Area| match vertical {
+            true => (area.0, area.2),
+            false => (area.1, area.3),
+        };
+
+        let (mut along_start, mut across_start) = (along(second).0, across(second).0);
+
+        if matches!(direction, CD::Vertical | CD::Horizontal) {
+            let (start, length) = across(previous);
+            let offset = length as i64 - across(second).1 as i64;
+
+            across_start = match align {
+                Align::Left => start,
+                Align::Center => start + offset / 2,
+                Align::Right => start + offset,
+                Align::Content => across_start,
+            };
+        }
+
+        let (first_start, first_length) = along(crop_area(canvas, canvas_crop));
+
+        if self.composite.gap > 0 && along_start > first_start {
+            let cut = (first_start + first_length as i64 - along_start).max(0) as u32;
+
+            match vertical {
+                true => canvas_crop.bottom += cut,
+                false => canvas_crop.right += cut,
+            }
+
+            along_start += self.composite.gap as i64;
+        }
+
+        match vertical {
+            true => (across_start, along_start, second.2, second.3),
+            false => (along_start, across_start, second.2, second.3),
+        }
+    }
+
+    /// Draws the images at the given placements, blending every image with the ones drawn before
+    /// it. Feeding it the placements [`Self::stitch`] returns gives back the stitched image.
+    pub fn composite(
+        images: &[RgbaImage],
+        placements: &[Placement],
+        composite: &CompositeOptions,
+    ) -> Result<RgbaImage, StitchError> {
+        let image = |placement: &Placement| {
+            images
+                .get(placement.index)
+                .ok_or(StitchError::PlacementOutOfRange {
+                    index: placement.index,
+                    images: images.len(),
+                })
+        };
+        let drawn = |placement: &Placement, image: &RgbaImage| {
+            let (x, y, width, height) = placement.crop.region(image.width(), image.height());
+            let area = (placement.x as i64, placement.y as i64, width, height);
+
+            (area, crop_imm(image, x, y, width, height).to_image())
+        };
+
+        let Some(placement) = placements.first() else {
+            return Err(StitchError::NoPlacements);
+        };
+
+        let (first_area, first_part) = drawn(placement, image(placement)?);
+        let mut canvas = first_area;
+        // Crops can cut earlier images off the final image, they are drawn anyway so blending
+        // sees the same pixels as when stitching one image at a time.
+        let mut full = canvas;
+        let mut steps = Vec::with_capacity(placements.len() - 1);
+
+        for placement in &placements[1..] {
+            let (second, part) = drawn(placement, image(placement)?);
+            let first = crop_area(canvas, &placement.canvas_crop);
+
+            steps.push((canvas, first, second, part));
+            canvas = bounding_area(first, second);
+            full = bounding_area(full, canvas);
+        }
+
+        let place = |area: Area| {
+            Rect::new(
+                (area.0 - full.0) as u32,
+                (area.1 - full.1) as u32,
+                area.2,
+                area.3,
+            )
+        };
+
+        let mut output = RgbaImage::new(full.2, full.3);
+        let first_at = place(first_area);
+        replace(
+            &mut output,
+            &first_part,
+            first_at.x as i64,
+            first_at.y as i64,
+        );
+
+        for (previous, first, second, part) in steps {
+            let (previous, first, second) = (place(previous), place(first), place(second));
+
+            Self::clear_outside(&mut output, previous, first);
+
+            if let Some(separator) = composite.separator {
+                Self::fill_gap(&mut output, first, second, separator.0);
+            }
+
+            let blended = overlap_to_blend(&output, first, second, composite).map(|overlap| {
+                let top_part =
+                    crop_imm(&output, overlap.x, overlap.y, overlap.width, overlap.height)
+                        .to_image();
+                (overlap, top_part)
+            });
+
+            replace(&mut output, &part, second.x as i64, second.y as i64);
+
+            if let Some((overlap, top_part)) = blended {
+                blend_overlap_part(
+                    &mut output,
+                    &top_part,
+                    first,
+                    &part,
+                    second,
+                    overlap,
+                    composite,
+                );
+            }
+        }
+
+        if full != canvas {
+            let canvas = place(canvas);
+            output = crop_imm(&output, canvas.x, canvas.y, canvas.width, canvas.height).to_image();
+        }
+
+        Ok(output)
+    }
+
+    /// Fills the space a gap leaves between `first` and the `second` image drawn after it with
+    /// `color`, across both of them.
+    fn fill_gap(image: &mut RgbaImage, first: Rect, second: Rect, color: Rgba<u8>) {
+        let (left, right) = (first.x.min(second.x), first.right().max(second.right()));
+        let (top, bottom) = (first.y.min(second.y), first.bottom().max(second.bottom()));
+
+        let gap = match (second.y >= first.bottom(), second.x >= first.right()) {
+            (true, _) => Rect::new(
+                left,
+                first.bottom(),
+                right - left,
+                second.y - first.bottom(),
+            ),
+            (false, true) => Rect::new(first.right(), top, second.x - first.right(), bottom - top),
+            (false, false) => return,
+        };
+
+        for y in gap.y..gap.bottom() {
+            for x in gap.x..gap.right() {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Puts the stitched image on [`CompositeOptions::background`] and adds
+    /// [`CompositeOptions::padding`] around it, the last step of stitching after
+    /// [`Self::composite`].
+    pub fn fill_canvas(image: RgbaImage, composite: &CompositeOptions) -> RgbaImage {
+        if composite.background.is_none() && composite.padding == 0 {
+            return image;
+        }
+
+        let padding = composite.padding;
+        let background = composite
+            .background
+            .map_or(Rgba([0, 0, 0, 0]), |color| color.0);
+        let mut output = RgbaImage::from_pixel(
+            image.width() + padding * 2,
+            image.height() + padding * 2,
+            background,
+        );
+
+        match composite.background {
+            Some(_) => {
+                for (x, y, pixel) in image.enumerate_pixels() {
+                    output.get_pixel_mut(x + padding, y + padding).blend(pixel);
+                }
+            }
+            None => replace(&mut output, &image, padding as i64, padding as i64),
+        }
+
+        output
+    }
+
+    /// Clears the pixels of `image` inside `outer` but outside `inner`.
+    fn clear_outside(image: &mut RgbaImage, outer: Rect, inner: Rect) {
+        for y in outer.y..outer.bottom() {
+            let spans = match (inner.y..inner.bottom()).contains(&y) {
+                true => [(outer.x, inner.x), (inner.right(), outer.right())],
+                false => [(outer.x, outer.right()), (0, 0)],
+            };
+
+            for x in spans.into_iter().flat_map(|(start, end)| start..end) {
+                image.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+            }
+        }
+    }
+
+    fn check_confidence(&self, region: &mut OverlapScore, seam: usize) -> Result<(), StitchError> {
+        let Some(min_confidence) = self.min_confidence else {
+            return Ok(());
+        };
+
+        if region.confidence.confidence >= min_confidence {
+            return Ok(());
+        }
+
+        match self.low_confidence {
+            LowConfidence::Fail => Err(StitchError::LowConfidence {
+                seam,
+                confidence: region.confidence.confidence,
+            }),
+            LowConfidence::Flag => {
+                region.confidence.flagged = true;
+                Ok(())
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), StitchError> {
+        if self.images.len() < 2 {
+            return Err(StitchError::TooFewImages(self.images.len()));
+        }
+
+        if let Exclusions::PerImage(exclusions) = &self.exclusions
+            && exclusions.len() != self.images.len()
+        {
+            return Err(StitchError::ExclusionCountMismatch {
+                images: self.images.len(),
+                exclusions: exclusions.len(),
+            });
+        }
+
+        if let (Order::Grid, Some(columns)) = (self.order, self.grid_columns)
+            && (columns == 0 || !self.images.len().is_multiple_of(columns))
+        {
+            return Err(StitchError::GridMismatch {
+                images: self.images.len(),
+                columns,
+            });
+        }
+
+        for (index, image) in self.images.iter().enumerate() {
+            self.validate_image(index, image)?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn validate_image(
+        &self,
+        index: usize,
+        image: &RgbaImage,
+    ) -> Result<(), StitchError> {
+        if self.window_size == 0 {
+            return Err(StitchError::EmptyWindow);
+        }
+
+        // Grid tiles are matched along both sides, the same as Auto can.
+        let direction = match self.order {
+            Order::Grid => CheckDirection::Auto,
+            Order::Ordered | Order::Unordered => self.direction,
+        };
+
+        let (length, breadth) = match direction {
+            CheckDirection::Horizontal => (image.width(), image.height()),
+            CheckDirection::Vertical
+            | CheckDirection::Sideways
+            | CheckDirection::SidewaysRight
+            | CheckDirection::SidewaysLeft => (image.height(), image.width()),
+            // Either side can end up along the stitch direction.
+            CheckDirection::Auto => (
+                image.width().min(image.height()),
+                image.width().max(image.height()),
+            ),
+        };
+
+        let size = match direction {
+            CheckDirection::Sideways
+            | CheckDirection::SidewaysRight
+            | CheckDirection::SidewaysLeft
+            | CheckDirection::Auto => length.min(breadth),
+            CheckDirection::Vertical | CheckDirection::Horizontal => length,
+        };
+
+        if self.crop >= size {
+            return Err(StitchError::CropLargerThanImage {
+                index,
+                crop: self.crop,
+                size,
+            });
+        }
+
+        if self.window_size > (length - self.crop) as usize {
+            return Err(StitchError::WindowLargerThanImage {
+                index,
+                window_size: self.window_size,
+                size: length - self.crop,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn add_to_positions_ordered(
+        positions: &mut VecDeque<Position>,
+        new_position: Position,
+        flipped: bool,
+    ) {
+        if positions.len() == 0 {
+            return positions.push_front(new_position);
+        }
+
+        match flipped {
+            true => {
+                for pos in positions.iter_mut() {
+                    pos.x += new_position.x;
+                    pos.y += new_position.y;
+                }
+
+                positions.push_back(new_position);
+            }
+            false => {
+                positions.push_front(new_position);
+            }
+        }
+    }
+
+    fn stack_images_with_overlap(
+        top_image: &RgbaImage,
+        bottom_image: &RgbaImage,
+        position: &Position,
+        flipped: bool,
+        composite: &CompositeOptions,
+    ) -> Result<RgbaImage, StitchError> {
+        let (top_image, bottom_image) = match flipped {
+            true => (bottom_image, top_image),
+            false => (top_image, bottom_image),
+        };
+
+        let top_width = top_image.width();
+        let top_height = top_image.height();
+        let bottom_width = bottom_image.width();
+        let bottom_height = bottom_image.height();
+
+        let overlap_x_abs = position.x.unsigned_abs();
+        let overlap_y_abs = position.y.unsigned_abs();
+
+        Self::check_overlap(position, top_image.dimensions(), bottom_image.dimensions())?;
+
+        let output_width = match position.x >= 0 {
+            true => (overlap_x_abs + bottom_width).max(top_width),
+            false => (top_width + overlap_x_abs).max(bottom_width),
+        };
+        let output_height = match position.y >= 0 {
+            true => (overlap_y_abs + bottom_height).max(top_height),
+            false => (top_height + overlap_y_abs).max(bottom_height),
+        };
+
+        let mut output_image = RgbaImage::new(output_width, output_height);
+
+        let copy_first_x = if position.x >= 0 { 0 } else { overlap_x_abs };
+        let copy_first_y = if position.y >= 0 { 0 } else { overlap_y_abs };
+
+        for y in 0..top_height {
+            for x in 0..top_width {
+                let pixel = top_image.get_pixel(x, y);
+                output_image.put_pixel(x + copy_first_x, y + copy_first_y, *pixel);
+            }
+        }
+
+        let copy_second_x = if position.x >= 0 { overlap_x_abs } else { 0 };
+        let copy_second_y = if position.y >= 0 { overlap_y_abs } else { 0 };
+
+        if let Some(separator) = composite.separator {
+            Self::fill_gap(
+                &mut output_image,
+                Rect::new(copy_first_x, copy_first_y, top_width, top_height),
+                Rect::new(copy_second_x, copy_second_y, bottom_width, bottom_height),
+                separator.0,
+            );
+        }
+
+        for y in 0..bottom_height {
+            for x in 0..bottom_width {
+                let output_x = copy_second_x + x;
+                let output_y = copy_second_y + y;
+
+                if output_y < output_height && output_x < output_width {
+                    let pixel = bottom_image.get_pixel(x, y);
+                    output_image.put_pixel(output_x, output_y, *pixel);
+                }
+            }
+        }
+
+        blend_overlap(
+            &mut output_image,
+            top_image,
+            Rect::new(copy_first_x, copy_first_y, top_width, top_height),
+            bottom_image,
+            Rect::new(copy_second_x, copy_second_y, bottom_width, bottom_height),
+            composite,
+        );
+
+        Ok(output_image)
+    }
+
+    fn check_overlap(
+        position: &Position,
+        (top_width, top_height): (u32, u32),
+        (bottom_width, bottom_height): (u32, u32),
+    ) -> Result<(), StitchError> {
+        if position.y.unsigned_abs() >= (bottom_height + top_height)
+            || position.x.unsigned_abs() >= (bottom_width + top_width)
+        {
+            return Err(StitchError::OverlapOutOfBounds {
+                position: position.clone(),
+                width: bottom_width + top_width,
+                height: bottom_height + top_height,
+            });
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn pixel_as_value<P>(pixel: &P) -> u64
+    where
+        P: Pixel,
+        P::Subpixel: Into<u64>,
+    {
+        let mut channels = pixel.channels();
+        let mut num_of_channels = channels.len();
+
+        if num_of_channels % 2 == 0 {
+            channels = &channels[..num_of_channels - 1];
+            num_of_channels -= 1;
+        }
+
+        channels
+            .iter()
+            .map(|p| <<P as Pixel>::Subpixel as Into<u64>>::into(*p))
+            .sum::<u64>()
+            / (num_of_channels as u64)
+    }
+
+    /// Returns the difference of the two rows along with the number of pixels it was summed over.
+    #[inline(always)]
+    fn row_diff_score(row1: &[u8], row2: &[u8], padding: i32) -> (u64, u64) {
+        let shift = padding.unsigned_abs() as usize;
+        let unmatched = shift.min(row1.len()).min(row2.len());
+
+        let (first_to_match, first_collection, second_to_match, second_collection) = match padding {
+            ..0 => (
+                &row1[row1.len() - unmatched..],
+                row1,
+                &row2[..unmatched],
+                &row2[shift.min(row2.len())..],
+            ),
+            _ => (
+                &row1[..unmatched],
+                &row1[shift.min(row1.len())..],
+                &row2[row2.len() - unmatched..],
+                row2,
+            ),
+        };
+
+        let mut score = first_to_match
+            .iter()
+            .chain(second_to_match)
+            .map(|p| *p as u64)
+            .sum::<u64>();
+
+        score += first_collection
+            .iter()
+            .zip(second_collection)
+            .map(|(p1, p2)| p1.abs_diff(*p2) as u64)
+            .sum::<u64>();
+
+        let count = 2 * unmatched + first_collection.len().min(second_collection.len());
+
+        (score, count as u64)
+    }
+
+    /// Same as [`Self::row_diff_score`], but pixels a mask marks as `0` are left out of both the
+    /// sum and the count. Also returns the count the rows would have without masks.
+    fn masked_row_diff_score(
+        (row1, mask1): (&[u8], Option<&[u8]>),
+        (row2, mask2): (&[u8], Option<&[u8]>),
+        padding: i32,
+    ) -> (u64, u64, u64) {
+        let shift = padding.unsigned_abs() as usize;
+        let unmatched = shift.min(row1.len()).min(row2.len());
+
+        let (first_unmatched, second_unmatched, first_offset, second_offset) = match padding {
+            ..0 => (
+                row1.len() - unmatched..row1.len(),
+                0..unmatched,
+                0,
+                shift.min(row2.len()),
+            ),
+            _ => (
+                0..unmatched,
+                row2.len() - unmatched..row2.len(),
+                shift.min(row1.len()),
+                0,
+            ),
+        };
+        let matched = (row1.len() - first_offset).min(row2.len() - second_offset);
+
+        let mut sum = 0;
+        let mut count = 0;
+
+        for (row, mask, indices) in [
+            (row1, mask1, first_unmatched),
+            (row2, mask2, second_unmatched),
+        ] {
+            for index in indices.filter(|index| Self::is_counted(mask, *index)) {
+                sum += row[index] as u64;
+                count += 1;
+            }
+        }
+
+        for (index1, index2) in (first_offset..).zip(second_offset..).take(matched) {
+            if Self::is_counted(mask1, index1) && Self::is_counted(mask2, index2) {
+                sum += row1[index1].abs_diff(row2[index2]) as u64;
+                count += 1;
+            }
+        }
+
+        (sum, count, (2 * unmatched + matched) as u64)
+    }
+
+    #[inline(always)]
+    fn is_counted(mask: Option<&[u8]>, index: usize) -> bool {
+        mask.is_none_or(|mask| mask[index] != 0)
+    }
+
+    /// Prepares `image` for matching, turned on its side when `rotated`.
+    fn match_input(&self, image: &RgbaImage, exclusions: &[Rect], rotated: bool) -> MatchInput {
+        let mask = Self::match_mask(image, exclusions, self.alpha_threshold);
+        let (image, mask) = match rotated {
+            true => (
+                Cow::Owned(rotate90(image)),
+                mask.map(|mask| rotate90(&mask)),
+            ),
+            false => (Cow::Borrowed(image), mask),
+        };
+        let image = match self.match_mode {
+            MatchMode::Normal | MatchMode::PhaseCorrelation | MatchMode::Ncc => image,
+            MatchMode::Edges => Cow::Owned(edge_detection(&image)),
+        };
+
+        MatchInput {
+            full: MatchPlane {
+                values: Self::match_plane(&image),
+                mask,
+            },
+            halves: OnceLock::new(),
+        }
+    }
+
+    fn match_plane(image: &RgbaImage) -> GrayImage {
+        let values = image
+            .pixels()
+            .map(|pixel| Self::pixel_as_value(pixel) as u8)
+            .collect();
+
+        GrayImage::from_raw(image.width(), image.height(), values).expect("same dimensions")
+    }
+
+    /// Marks the pixels that take part in matching with `255` and the ones that are excluded or
+    /// have an alpha below the threshold with `0`, returns `None` when every pixel takes part.
+    fn match_mask(
+        image: &RgbaImage,
+        exclusions: &[Rect],
+        alpha_threshold: u8,
+    ) -> Option<GrayImage> {
+        let bounds = Rect::new(0, 0, image.width(), image.height());
+        let exclusions: Vec<Rect> = exclusions
+            .iter()
+            .filter_map(|rect| rect.intersection(&bounds))
+            .collect();
+
+        if exclusions.is_empty() && image.pixels().all(|pixel| pixel[3] >= alpha_threshold) {
+            return None;
+        }
+
+        Some(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            let counted = image.get_pixel(x, y)[3] >= alpha_threshold
+                && !exclusions.iter().any(|rect| rect.contains(x, y));
+
+            Luma([if counted { 255 } else { 0 }])
+        }))
+    }
+
+    #[inline(always)]
+    fn plane_row(plane: &GrayImage, y: u32) -> &[u8] {
+        let width = plane.width() as usize;
+        let start = y as usize * width;
+
+        &plane.as_raw()[start..start + width]
+    }
+
+    fn offset_score(level: &MatchLevel, offset: (i32, i32)) -> Candidate {
+        match level.metric {
+            Metric::Difference => Self::difference_score(level, offset),
+            Metric::Ncc => Self::ncc_score(level, offset),
+        }
+    }
+
+    fn difference_score(level: &MatchLevel, offset: (i32, i32)) -> Candidate {
+        if level.first_mask.is_some() || level.second_mask.is_some() {
+            return Self::masked_difference_score(level, offset);
+        }
+
+        let (y, x) = offset;
+        let (first_start, second_start) = level.window_starts(y);
+        let mut sum = 0;
+        let mut count = 0;
+
+        for row in 0..level.window_size {
+            let (row_sum, row_count) = Self::row_diff_score(
+                Self::plane_row(level.first, first_start + row),
+                Self::plane_row(level.second, second_start + row),
+                x,
+            );
+
+            sum += row_sum;
+            count += row_count;
+        }
+
+        let mean_difference = sum as f64 / count.max(1) as f64;
+
+        Candidate {
+            score: (u64::MAX - sum) / level.window_size as u64,
+            mean_difference,
+            mismatch: mean_difference / 255.0,
+            offset,
+        }
+    }
+
+    /// Scores the window leaving out masked pixels. The sum is scaled back up to the count the
+    /// window would have without masks, so the score stays comparable with unmasked windows.
+    fn masked_difference_score(level: &MatchLevel, offset: (i32, i32)) -> Candidate {
+        let (y, x) = offset;
+        let (first_start, second_start) = level.window_starts(y);
+        let mut sum = 0;
+        let mut count = 0;
+        let mut full_count = 0;
+
+        for row in 0..level.window_size {
+            let (first_mask, second_mask) = level.mask_rows(first_start + row, second_start + row);
+
+            let (row_sum, row_count, row_full_count) = Self::masked_row_diff_score(
+                (Self::plane_row(level.first, first_start + row), first_mask),
+                (
+                    Self::plane_row(level.second, second_start + row),
+                    second_mask,
+                ),
+                x,
+            );
+
+            sum += row_sum;
+            count += row_count;
+            full_count += row_full_count;
+        }
+
+        // Nothing left to compare, so there is nothing that speaks for this offset.
+        if count == 0 {
+            return Candidate {
+                score: 0,
+                mean_difference: 255.0,
+                mismatch: 1.0,
+                offset,
+            };
+        }
+
+        let mean_difference = sum as f64 / count as f64;
+
+        Candidate {
+            score: (u64::MAX - sum * full_count / count) / level.window_size as u64,
+            mean_difference,
+            mismatch: mean_difference / 255.0,
+            offset,
+        }
+    }
+
+    /// Scores the window with zero-mean normalized cross-correlation, so a brightness or
+    /// contrast change between the images does not change the score. The correlation is scaled
+    /// down by how much of the rows overlap, otherwise a sliver of overlap would correlate just as
+    /// well as the full width.
+    fn ncc_score(level: &MatchLevel, offset: (i32, i32)) -> Candidate {
+        let (y, x) = offset;
+        let (first_start, second_start) = level.window_starts(y);
+        let shift = x.unsigned_abs() as usize;
+
+        let (mut first_sum, mut second_sum) = (0u64, 0u64);
+        let (mut first_squares, mut second_squares, mut products) = (0u64, 0u64, 0u64);
+        let (mut matched, mut total) = (0usize, 0usize);
+
+        for row in 0..level.window_size {
+            let row1 = Self::plane_row(level.first, first_start + row);
+            let row2 = Self::plane_row(level.second, second_start + row);
+            let (mask1, mask2) = level.mask_rows(first_start + row, second_start + row);
+            let unmatched = shift.min(row1.len()).min(row2.len());
+
+            let (offset1, offset2) = match x {
+                ..0 => (0, shift.min(row2.len())),
+                _ => (shift.min(row1.len()), 0),
+            };
+            let row_matched = (row1.len() - offset1).min(row2.len() - offset2);
+            let mut skipped = 0;
+
+            for (index1, index2) in (offset1..).zip(offset2..).take(row_matched) {
+                if !Self::is_counted(mask1, index1) || !Self::is_counted(mask2, index2) {
+                    skipped += 1;
+                    continue;
+                }
+
+                let (p1, p2) = (row1[index1] as u64, row2[index2] as u64);
+
+                first_sum += p1;
+                second_sum += p2;
+                first_squares += p1 * p1;
+                second_squares += p2 * p2;
+                products += p1 * p2;
+            }
+
+            matched += row_matched - skipped;
+            total += row_matched - skipped + 2 * unmatched;
+        }
+
+        let count = matched.max(1) as f64;
+        let first_variance = first_squares as f64 - (first_sum as f64).powi(2) / count;
+        let second_variance = second_squares as f64 - (second_sum as f64).powi(2) / count;
+        let covariance = products as f64 - first_sum as f64 * second_sum as f64 / count;
+
+        // Two flat windows match no matter their brightness, a flat and a textured one do not.
+        let correlation = match (first_variance > 0.5, second_variance > 0.5) {
+            (true, true) => covariance / (first_variance * second_variance).sqrt(),
+            (false, false) => 1.0,
+            _ => 0.0,
+        };
+        let correlation = correlation.clamp(-1.0, 1.0) * matched as f64 / total.max(1) as f64;
+
+        Candidate {
+            score: ((correlation + 1.0) / 2.0 * u64::MAX as f64) as u64,
+            mean_difference: ((first_variance + second_variance - 2.0 * covariance).max(0.0)
+                / count)
+                .sqrt(),
+            // The correlation does not depend on brightness or contrast, the difference does.
+            mismatch: (1.0 - correlation).clamp(0.0, 1.0),
+            offset,
+        }
+    }
+
+    /// Scores every offset of the level, returning the best one of every row.
+    /// Rows left once the scan stops are skipped.
+    fn best_offset(level: &MatchLevel, scan: &Scan) -> Vec<Candidate> {
+        let (horizontal_start, horizontal_end) = level.horizontal;
+        let row_size = (horizontal_end - horizontal_start + 1) as usize;
+
+        scan.expect(level.offset_count());
+
+        (level.rows.0..=level.rows.1)
+            .into_par_iter()
+            .filter_map(|y| {
+                if scan.stopped() {
+                    return None;
+                }
+
+                let best = (horizontal_start..=horizontal_end)
+                    .map(|x| Self::offset_score(level, (y, x)))
+                    .max_by_key(|candidate| candidate.rank(level.deterministic));
+
+                scan.advance(row_size);
+                best
+            })
+            .collect()
+    }
+
+    fn best_offsets(level: &MatchLevel, offsets: Vec<(i32, i32)>, scan: &Scan) -> Vec<Candidate> {
+        let mut scored: Vec<_> = offsets
+            .into_par_iter()
+            .map(|offset| {
+                let candidate = Self::offset_score(level, offset);
+
+                scan.advance(1);
+                candidate
+            })
+            .collect();
+
+        scored.sort_unstable_by_key(|candidate| Reverse(candidate.rank(level.deterministic)));
+        scored
+    }
+
+    /// Returns every offset scored on the full resolution level, best first. Once the scan stops
+    /// only the best candidate is followed down the rest of the levels.
+    fn pyramid_search(
+        base: MatchLevel,
+        (first, second): (&MatchInput, &MatchInput),
+        scan: &Scan,
+    ) -> Vec<Candidate> {
+        let mut levels = vec![base];
+
+        for (first, second) in first.halves().iter().zip(second.halves()) {
+            match levels
+                .last()
+                .expect("has base level")
+                .downscale(first, second)
+            {
+                Some(level) => levels.push(level),
+                None => break,
+            }
+        }
+
+        let coarsest = levels.pop().expect("has base level");
+        let neighbourhood_size = (2 * PYRAMID_REFINE_RADIUS as usize + 2).pow(2);
+
+        // How many offsets the finer levels score depends on how the candidates spread out.
+        scan.expect(
+            coarsest.offset_count() + levels.len() * PYRAMID_CANDIDATES * neighbourhood_size,
+        );
+
+        let mut candidates = Self::best_offsets(&coarsest, coarsest.offsets(), scan);
+
+        for level in levels.iter().rev() {
+            let followed = match scan.stopped() {
+                true => 1,
+                false => PYRAMID_CANDIDATES,
+            };
+            let neighbourhood = candidates
+                .iter()
+                .take(followed)
+                .flat_map(|candidate| {
+                    let (y, x) = candidate.offset;
+                    level.neighbourhood(y * 2, x * 2)
+                })
+                .sorted_unstable()
+                .dedup()
+                .collect();
+
+            candidates = Self::best_offsets(level, neighbourhood, scan);
+        }
+
+        candidates
+    }
+
+    /// Scores the strongest phase correlation peaks, strongest first.
+    fn phase_correlation_search(level: &MatchLevel) -> Vec<Candidate> {
+        // The rows of the first image the second one can still overlap at the last allowed row.
+        let second_rows = level.second.height() - level.second_start;
+        let first_rows = (level.rows.1 + second_rows as i32).max(0) as u32;
+
+        phase_correlation_peaks(
+            (level.first, level.first_mask),
+            first_rows.min(level.first.height()),
+            (level.second, level.second_mask),
+            level.second_start,
+            level.rows,
+            level.horizontal,
+            PHASE_CORRELATION_PEAKS,
+        )
+        .into_par_iter()
+        .map(|offset| Self::offset_score(level, offset))
+        .collect()
+    }
+
+    /// Rates how much the picked candidate can be trusted, comparing it against the best
+    /// candidate that is not just a slight shift of it.
+    fn rate_candidates(
+        best: Candidate,
+        candidates: &[Candidate],
+        deterministic: bool,
+    ) -> MatchConfidence {
+        let runner_up = candidates
+            .iter()
+            .filter(|candidate| {
+                let (y, x) = candidate.offset;
+                (y - best.offset.0).abs().max((x - best.offset.1).abs()) > AMBIGUITY_RADIUS
+            })
+            .max_by_key(|candidate| candidate.rank(deterministic));
+
+        let ambiguity = match runner_up {
+            Some(runner_up) if runner_up.mismatch > 0.0 => {
+                (best.mismatch / runner_up.mismatch).min(1.0)
+            }
+            Some(_) => 1.0,
+            None => 0.0,
+        };
+
+        MatchConfidence {
+            mean_difference: best.mean_difference,
+            ambiguity,
+            confidence: (1.0 - best.mismatch) * (1.0 - ambiguity),
+            ..Default::default()
+        }
+    }
+
+    /// Turns `hint` into the offsets the first line of the second image is allowed at along
+    /// `direction`.
+    fn hint_bounds(hint: &PairHint, direction: CheckDirection) -> HintBounds {
+        use CheckDirection as CD;
+
+        let PairHint { hint, previous } = hint;
+
+        // Lines run along the stitch direction, horizontal images are matched rotated.
+        let lines = match direction {
+            CD::Horizontal => previous.0,
+            CD::Vertical | CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft | CD::Auto => {
+                previous.1
+            }
+        };
+
+        match hint {
+            OffsetHint::Expected { offset, tolerance } => {
+                let tolerance = *tolerance as i64;
+                let (along, across) = match direction {
+                    CD::Horizontal => (offset.x, offset.y),
+                    CD::Vertical
+                    | CD::Sideways
+                    | CD::SidewaysRight
+                    | CD::SidewaysLeft
+                    | CD::Auto => (offset.y, offset.x),
+                };
+                let (along, across) = (along as i64, across as i64);
+
+                HintBounds {
+                    lines: (along - tolerance, along + tolerance),
+                    horizontal: match direction {
+                        CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft => {
+                            Some((across - tolerance, across + tolerance))
+                        }
+                        CD::Vertical | CD::Horizontal | CD::Auto => None,
+                    },
+                }
+            }
+            OffsetHint::Overlap { min, max } => HintBounds {
+                lines: (lines as i64 - *max as i64, lines as i64 - *min as i64),
+                horizontal: None,
+            },
+        }
+    }
+
+    fn find_stitch_region(
+        &self,
+        (first, second): (&MatchImage, &MatchImage),
+        hint: Option<PairHint>,
+        pair: Option<(usize, usize)>,
+    ) -> Result<OverlapScore, StitchError> {
+        use CheckDirection as CD;
+
+        let find = |direction| {
+            self.find_directed_stitch_region(
+                (first.input(self, direction), second.input(self, direction)),
+                hint.as_ref(),
+                &Scan::new(self, pair, direction),
+            )
+        };
+        // A direction the images cannot be matched along loses against one they can.
+        let more_confident =
+            |best: Result<OverlapScore, StitchError>, other: Result<OverlapScore, StitchError>| {
+                match (best, other) {
+                    (Ok(best), Ok(other)) => {
+                        match other.confidence.confidence > best.confidence.confidence {
+                            true => Ok(other),
+                            false => Ok(best),
+                        }
+                    }
+                    (Err(_), other) => other,
+                    (best, Err(_)) => best,
+                }
+            };
+
+        self.observe_pair(pair, || match self.direction {
+            CD::Auto => {
+                let best = more_confident(find(CD::Vertical), find(CD::Horizontal));
+
+                match &best {
+                    Ok(region) if region.confidence.confidence >= AUTO_SIDEWAYS_CONFIDENCE => best,
+                    _ => more_confident(best, find(CD::Sideways)),
+                }
+            }
+            direction => find(direction),
+        })
+    }
+
+    /// Tells the observer when the search for `pair` starts and what it found, searches without
+    /// a pair are not reported.
+    fn observe_pair(
+        &self,
+        pair: Option<(usize, usize)>,
+        find: impl FnOnce() -> Result<OverlapScore, StitchError>,
+    ) -> Result<OverlapScore, StitchError> {
+        let Some((first, second)) = pair else {
+            return find();
+        };
+
+        self.notify(StitchEvent::PairStarted { first, second });
+        let region = find()?;
+        self.notify(StitchEvent::PairFinished {
+            first,
+            second,
+            confidence: region.confidence.clone(),
+        });
+
+        Ok(region)
+    }
+
+    fn notify(&self, event: StitchEvent) {
+        if let Some(observer) = &self.observer {
+            observer.0.on_event(event);
+        }
+    }
+
+    pub(super) fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+
+    fn check_cancelled(&self) -> Result<(), StitchError> {
+        match self.is_cancelled() {
+            true => Err(StitchError::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /// Finds the stitch region along the direction of `scan`, [`CheckDirection::Auto`] is
+    /// resolved by [`Self::find_stitch_region`] before it gets here.
+    fn find_directed_stitch_region(
+        &self,
+        (first_input, second_input): (&MatchInput, &MatchInput),
+        hint: Option<&PairHint>,
+        scan: &Scan,
+    ) -> Result<OverlapScore, StitchError> {
+        use CheckDirection as CD;
+
+        let direction = scan.direction();
+        let crop = self.crop;
+        let (first, second) = (&first_input.full, &second_input.full);
+        // Searches without a pair only try out scales, their errors are not reported.
+        let (first_index, second_index) = scan.pair().unwrap_or_default();
+
+        let (horizontal_start, horizontal_move_end) = match direction {
+            CD::Sideways | CD::SidewaysRight | CD::SidewaysLeft => {
+                let width = second.values.width().max(first.values.width());
+                let start = -((width - 1 - crop) as i32);
+                let end = (width - crop) as i32;
+
+                (
+                    match direction {
+                        CD::Sideways | CD::SidewaysLeft => start,
+                        CD::SidewaysRight => 0,
+                        _ => unreachable!(),
+                    },
+                    match direction {
+                        CD::SidewaysRight | CD::Sideways => end,
+                        CD::SidewaysLeft => 0,
+                        _ => unreachable!(),
+                    },
+                )
+            }
+            _ => (0, 0),
+        };
+
+        let window_size =
+            (self.window_size as u32).min(second.values.height().saturating_sub(crop));
+
+        // A banner or overlay left out of matching can cover the rows right after the crop, the
+        // window moves down past it so there is something left to compare.
+        let second_start =
+            second
+                .counted_rows(crop, window_size)
+                .ok_or(StitchError::NothingToMatch {
+                    first: first_index,
+                    second: second_index,
+                })?;
+        let skipped = (second_start - crop) as i32;
+
+        let rows = (
+            MatchLevel::lowest_row(&second.values, second_start, window_size),
+            first.values.height().saturating_sub(crop) as i32 - window_size as i32,
+        );
+        let horizontal = (horizontal_start, horizontal_move_end);
+
+        // Offsets are where the first row of the window lands, the hints are about the first row
+        // of the second image.
+        let (rows, horizontal) = match hint.map(|hint| Self::hint_bounds(hint, direction)) {
+            Some(bounds) => (
+                (
+                    rows.0
+                        .max(clamp_offset(bounds.lines.0 + second_start as i64)),
+                    rows.1
+                        .min(clamp_offset(bounds.lines.1 + second_start as i64)),
+                ),
+                match bounds.horizontal {
+                    Some((start, end)) => (
+                        horizontal.0.max(clamp_offset(start)),
+                        horizontal.1.min(clamp_offset(end)),
+                    ),
+                    None => horizontal,
+                },
+            ),
+            None => (rows, horizontal),
+        };
+
+        if hint.is_some() && (rows.0 > rows.1 || horizontal.0 > horizontal.1) {
+            return Err(StitchError::HintOutOfRange {
+                first: first_index,
+                second: second_index,
+            });
+        }
+
+        let base = MatchLevel {
+            rows,
+            horizontal,
+            second_start,
+            window_size,
+            first: &first.values,
+            second: &second.values,
+            first_mask: first.mask.as_ref(),
+            second_mask: second.mask.as_ref(),
+            metric: match self.match_mode {
+                MatchMode::Ncc => Metric::Ncc,
+                MatchMode::Normal | MatchMode::Edges | MatchMode::PhaseCorrelation => {
+                    Metric::Difference
+                }
+            },
+            deterministic: self.deterministic,
+        };
+
+        let candidates = match base.is_empty() {
+            true => Vec::new(),
+            false => match (&self.match_mode, self.search_mode) {
+                (MatchMode::PhaseCorrelation, _) => Self::phase_correlation_search(&base),
+                (_, SearchMode::Exhaustive) => {
+                    let mut candidates = Self::best_offset(&base, scan);
+
+                    // The rows left once the time ran out could hold the match, the pyramid
+                    // always scores a coarse pass over all of them.
+                    if scan.truncated() {
+                        candidates.extend(Self::pyramid_search(
+                            base,
+                            (first_input, second_input),
+                            scan,
+                        ));
+                    }
+
+                    candidates
+                }
+                (_, SearchMode::Pyramid) => {
+                    Self::pyramid_search(base, (first_input, second_input), scan)
+                }
+            },
+        };
+        scan.finish();
+
+        let best = candidates
+            .iter()
+            .max_by_key(|candidate| candidate.rank(self.deterministic));
+
+        // Positions are where row `crop` of the second image lands.
+        Ok(match best {
+            Some(&best) => OverlapScore {
+                score: best.score,
+                confidence: MatchConfidence {
+                    direction,
+                    truncated: scan.truncated(),
+                    ..Self::rate_candidates(best, &candidates, self.deterministic)
+                },
+                position: match (direction, best.offset) {
+                    (
+                        CD::Vertical
+                        | CD::Sideways
+                        | CD::SidewaysRight
+                        | CD::SidewaysLeft
+                        | CD::Auto,
+                        (y, x),
+                    ) => Position { y: y - skipped, x },
+                    (CD::Horizontal, (y, _)) => Position {
+                        x: y - skipped,
+                        y: 0,
+                    },
+                },
+                flipped: false,
+            },
+            // Nothing was scored, so it loses against any offset that was.
+            None => OverlapScore {
+                score: 0,
+                position: Position::default(),
+                flipped: false,
+                confidence: MatchConfidence {
+                    direction,
+                    truncated: scan.truncated(),
+                    ..Default::default()
+                },
+            },
+        })
+    }
+
+    pub fn stitch_images(
+        part1: &RgbaImage,
+        part2: &RgbaImage,
+        position: &Position,
         flipped: bool,
         crop: u32,
         crop_direction: CheckDirection,
-    ) -> RgbaImage {
+        composite: &CompositeOptions,
+    ) -> Result<RgbaImage, StitchError> {
+        let position = &Self::stitch_position(position, crop, crop_direction);
+        let crop = Self::stitch_crop(position, crop, crop_direction);
+
+        let part1 = crop.crop_image(part1);
+        let part2 = crop.reverse().crop_image(part2);
+
+        Self::stack_images_with_overlap(&part1, &part2, position, flipped, composite)
+    }
+
+    /// Moves the exclusions of both images to where [`Self::stitch_images`] places the images,
+    /// so they keep applying once the images are stitched into one.
+    fn stitch_exclusions(
+        (part1, part2): (&RgbaImage, &RgbaImage),
+        (exclusions1, exclusions2): (&[Rect], &[Rect]),
+        position: &Position,
+        flipped: bool,
+        crop: u32,
+        crop_direction: CheckDirection,
+    ) -> Vec<Rect> {
+        let crop = Self::stitch_crop(position, crop, crop_direction);
+
+        let place = |image: &RgbaImage, crop: &ImageCrop, exclusions: &[Rect], (x, y)| {
+            let (left, top, width, height) = crop.region(image.width(), image.height());
+            let kept = Rect::new(left, top, width, height);
+
+            exclusions
+                .iter()
+                .filter_map(|rect| rect.intersection(&kept))
+                .map(|rect| Rect::new(rect.x - left + x, rect.y - top + y, rect.width, rect.height))
+                .collect::<Vec<_>>()
+        };
+
+        let (part1_at, part2_at) = Self::stitch_offsets(position, flipped);
+
+        let mut exclusions = place(part1, &crop, exclusions1, part1_at);
+        exclusions.extend(place(part2, &crop.reverse(), exclusions2, part2_at));
+        exclusions
+    }
+
+    /// Where [`Self::stitch_images`] places the first and second image.
+    fn stitch_offsets(position: &Position, flipped: bool) -> ((u32, u32), (u32, u32)) {
+        let first_at = (
+            if position.x >= 0 {
+                0
+            } else {
+                position.x.unsigned_abs()
+            },
+            if position.y >= 0 {
+                0
+            } else {
+                position.y.unsigned_abs()
+            },
+        );
+        let second_at = (
+            if position.x >= 0 {
+                position.x.unsigned_abs()
+            } else {
+                0
+            },
+            if position.y >= 0 {
+                position.y.unsigned_abs()
+            } else {
+                0
+            },
+        );
+
+        match flipped {
+            true => (second_at, first_at),
+            false => (first_at, second_at),
+        }
+    }
+
+    /// Crop of the first image, the second one gets the [`ImageCrop::reverse`] of it. The edges
+    /// facing each other are cut, so the sides flip when the second image goes before the first.
+    fn stitch_crop(position: &Position, crop: u32, crop_direction: CheckDirection) -> ImageCrop {
         use CheckDirection::*;
 
-        let crop = match crop_direction {
-            Vertical => ImageCrop {
+        match crop_direction {
+            Vertical | Auto if position.y < 0 => ImageCrop {
+                top: crop,
+                ..Default::default()
+            },
+            Vertical | Auto => ImageCrop {
                 bottom: crop,
                 ..Default::default()
             },
+            Horizontal if position.x < 0 => ImageCrop {
+                left: crop,
+                ..Default::default()
+            },
             Horizontal => ImageCrop {
                 right: crop,
                 ..Default::default()
             },
             Sideways | SidewaysRight | SidewaysLeft => {
-                let mut crop_obj = ImageCrop {
-                    bottom: crop,
-                    ..Default::default()
-                };
+                let mut crop_obj = ImageCrop::default();
+
+                if position.y < 0 {
+                    crop_obj.top = crop
+                } else {
+                    crop_obj.bottom = crop
+                }
 
                 if position.x < 0 {
                     crop_obj.right = crop
@@ -548,11 +2598,354 @@ impl ImageStitcher {
 
                 crop_obj
             }
+        }
+    }
+
+    /// Offset of the second image from the first once both are cut by [`Self::stitch_crop`].
+    /// Matches skip the first `crop` rows of the second image, when it goes before the first one
+    /// those rows are cut from the other end so it moves back by both crops.
+    fn stitch_position(position: &Position, crop: u32, crop_direction: CheckDirection) -> Position {
+        use CheckDirection::*;
+
+        let back = 2 * crop as i32;
+
+        match crop_direction {
+            Horizontal if position.x < 0 => Position {
+                x: position.x - back,
+                ..position.clone()
+            },
+            Vertical | Auto | Sideways | SidewaysRight | SidewaysLeft if position.y < 0 => {
+                Position {
+                    y: position.y - back,
+                    ..position.clone()
+                }
+            }
+            _ => position.clone(),
+        }
+    }
+}
+
+/// `(x, y, width, height)` of an image while laying out a stitch, relative to the first image so
+/// it can go negative.
+type Area = (i64, i64, u32, u32);
+
+/// Resamples `image` by `scale`, keeping at least one pixel on each side.
+fn scale_image(image: &RgbaImage, scale: f64) -> RgbaImage {
+    let side = |length: u32| ((length as f64 * scale).round() as u32).max(1);
+
+    resize(
+        image,
+        side(image.width()),
+        side(image.height()),
+        FilterType::CatmullRom,
+    )
+}
+
+fn scale_rects(rects: &[Rect], scale: f64) -> Vec<Rect> {
+    let scaled = |value: u32| (value as f64 * scale).round() as u32;
+
+    rects
+        .iter()
+        .map(|rect| {
+            Rect::new(
+                scaled(rect.x),
+                scaled(rect.y),
+                scaled(rect.width),
+                scaled(rect.height),
+            )
+        })
+        .collect()
+}
+
+fn crop_area(area: Area, crop: &ImageCrop) -> Area {
+    let (x, y, width, height) = crop.region(area.2, area.3);
+    (area.0 + x as i64, area.1 + y as i64, width, height)
+}
+
+fn bounding_area(first: Area, second: Area) -> Area {
+    let (x, y) = (first.0.min(second.0), first.1.min(second.1));
+    let right = (first.0 + first.2 as i64).max(second.0 + second.2 as i64);
+    let bottom = (first.1 + first.3 as i64).max(second.1 + second.3 as i64);
+    (x, y, (right - x) as u32, (bottom - y) as u32)
+}
+
+/// What a [`super::StitchSession`] has put together so far, the positions are newest first.
+#[derive(Debug, Default)]
+pub(super) struct OrderedStitch {
+    pub(super) image: Option<RgbaImage>,
+    /// Where the last image sits on the stitched image, uncropped.
+    pub(super) last: Area,
+    pub(super) exclusions: Vec<Rect>,
+    pub(super) positions: VecDeque<Position>,
+    pub(super) seams: Vec<MatchConfidence>,
+}
+
+/// An [`OffsetHint`] for a pair along with the size of the first image of the pair.
+struct PairHint<'a> {
+    hint: &'a OffsetHint,
+    previous: (u32, u32),
+}
+
+/// Inclusive ranges the first line of the second image is allowed at along the stitch direction
+/// and, when the images can move sideways, across it.
+#[derive(Debug, Clone, Copy)]
+struct HintBounds {
+    lines: (i64, i64),
+    horizontal: Option<(i64, i64)>,
+}
+
+fn clamp_offset(offset: i64) -> i32 {
+    offset.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// A scored offset, `offset` is `(row, horizontal)` in the coordinates of the level it was scored on.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    score: u64,
+    mean_difference: f64,
+    /// How far the windows are from matching, from `0` for a perfect match up to `1`.
+    mismatch: f64,
+    offset: (i32, i32),
+}
+
+impl Candidate {
+    /// Orders candidates by score. Deterministic ties go to the offset past the start of the first
+    /// image that overlaps it the least, so blank images are not stacked onto each other, then to
+    /// the smallest shift across it. Otherwise they go to whichever candidate happens to be
+    /// compared last.
+    fn rank(&self, deterministic: bool) -> (u64, (bool, i32, Reverse<u32>, bool)) {
+        let (y, x) = self.offset;
+
+        (
+            self.score,
+            match deterministic {
+                true => (y >= 0, y, Reverse(x.unsigned_abs()), x >= 0),
+                false => Default::default(),
+            },
+        )
+    }
+}
+
+/// One level of the match search, holding the value planes of both images at a single scale
+/// along with the inclusive ranges of row and horizontal offsets that are allowed on it.
+struct MatchLevel<'a> {
+    first: &'a GrayImage,
+    second: &'a GrayImage,
+    second_start: u32,
+    window_size: u32,
+    /// Pixels marked with `0` are left out of the scores, `None` when every pixel counts.
+    first_mask: Option<&'a GrayImage>,
+    second_mask: Option<&'a GrayImage>,
+    rows: (i32, i32),
+    horizontal: (i32, i32),
+    metric: Metric,
+    deterministic: bool,
+}
+
+/// An image along with its exclusions, prepared for matching along each direction the first
+/// time it is matched that way so it can be matched against several images.
+struct MatchImage<'a> {
+    image: &'a RgbaImage,
+    exclusions: &'a [Rect],
+    upright: OnceLock<MatchInput>,
+    /// Horizontal stitches are matched on the image turned on its side.
+    rotated: OnceLock<MatchInput>,
+}
+
+impl<'a> MatchImage<'a> {
+    fn new(image: &'a RgbaImage, exclusions: &'a [Rect]) -> Self {
+        Self {
+            image,
+            exclusions,
+            upright: OnceLock::new(),
+            rotated: OnceLock::new(),
+        }
+    }
+
+    fn input(&self, stitcher: &ImageStitcher, direction: CheckDirection) -> &MatchInput {
+        let (input, rotated) = match direction {
+            CheckDirection::Horizontal => (&self.rotated, true),
+            CheckDirection::Vertical
+            | CheckDirection::Sideways
+            | CheckDirection::SidewaysRight
+            | CheckDirection::SidewaysLeft
+            | CheckDirection::Auto => (&self.upright, false),
+        };
+
+        input.get_or_init(|| stitcher.match_input(self.image, self.exclusions, rotated))
+    }
+}
+
+/// The plane of an image that gets matched, along with the halved planes
+/// [`SearchMode::Pyramid`] searches on which are only made once they are needed.
+struct MatchInput {
+    full: MatchPlane,
+    halves: OnceLock<Vec<MatchPlane>>,
+}
+
+impl MatchInput {
+    fn halves(&self) -> &[MatchPlane] {
+        self.halves.get_or_init(|| {
+            let mut halves: Vec<MatchPlane> = Vec::new();
+
+            while halves.len() < PYRAMID_MAX_LEVELS {
+                match halves.last().unwrap_or(&self.full).half() {
+                    Some(half) => halves.push(half),
+                    None => break,
+                }
+            }
+
+            halves
+        })
+    }
+}
+
+struct MatchPlane {
+    values: GrayImage,
+    /// Pixels marked with `0` are left out of the scores, `None` when every pixel counts.
+    mask: Option<GrayImage>,
+}
+
+impl MatchPlane {
+    /// First row from `start` on that begins `length` rows which all have a pixel that counts,
+    /// `None` when there are no such rows.
+    fn counted_rows(&self, start: u32, length: u32) -> Option<u32> {
+        let Some(mask) = &self.mask else {
+            return Some(start);
         };
+        let mut run = 0;
+
+        for y in start..mask.height() {
+            match ImageStitcher::plane_row(mask, y)
+                .iter()
+                .any(|&value| value != 0)
+            {
+                true => run += 1,
+                false => run = 0,
+            }
 
-        let part1 = crop.crop_image(part1);
-        let part2 = crop.reverse().crop_image(part2);
+            if run == length {
+                return Some(y + 1 - length);
+            }
+        }
 
-        Self::stack_images_with_overlap(&part1, &part2, &position, flipped)
+        None
+    }
+
+    /// Halves the plane, returns `None` once it gets too small to be worth searching on.
+    fn half(&self) -> Option<Self> {
+        if self.values.width().min(self.values.height()) < PYRAMID_MIN_SIZE * 2 {
+            return None;
+        }
+
+        let half = |plane: &GrayImage| {
+            resize(
+                plane,
+                plane.width() / 2,
+                plane.height() / 2,
+                FilterType::Triangle,
+            )
+        };
+
+        Some(Self {
+            values: half(&self.values),
+            // A downscaled pixel only counts when most of what it covers did.
+            mask: self.mask.as_ref().map(|mask| {
+                let mut mask = half(mask);
+                mask.pixels_mut()
+                    .for_each(|pixel| pixel[0] = if pixel[0] >= 128 { 255 } else { 0 });
+                mask
+            }),
+        })
+    }
+}
+
+/// How a window of the second plane is compared against the first one.
+#[derive(Debug, Clone, Copy)]
+enum Metric {
+    Difference,
+    Ncc,
+}
+
+impl<'a> MatchLevel<'a> {
+    fn mask_rows(&self, first_row: u32, second_row: u32) -> (Option<&[u8]>, Option<&[u8]>) {
+        (
+            self.first_mask
+                .map(|mask| ImageStitcher::plane_row(mask, first_row)),
+            self.second_mask
+                .map(|mask| ImageStitcher::plane_row(mask, second_row)),
+        )
+    }
+
+    /// Rows of the first and second plane the window starts on for a row offset.
+    ///
+    /// A negative offset means the second image starts above the first one, so the window
+    /// moves down the second image instead and is matched against the top of the first.
+    fn window_starts(&self, y: i32) -> (u32, u32) {
+        match y >= 0 {
+            true => (y as u32, self.second_start),
+            false => (0, self.second_start + y.unsigned_abs()),
+        }
+    }
+
+    /// Row offset of the second image starting as far above the first one as it can while its
+    /// last window of rows still overlaps it.
+    fn lowest_row(second: &GrayImage, second_start: u32, window_size: u32) -> i32 {
+        -((second.height() - second_start - window_size) as i32)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.window_size == 0 || self.rows.0 > self.rows.1 || self.horizontal.0 > self.horizontal.1
+    }
+
+    fn offset_count(&self) -> usize {
+        (self.rows.1 - self.rows.0 + 1).max(0) as usize
+            * (self.horizontal.1 - self.horizontal.0 + 1).max(0) as usize
+    }
+
+    fn offsets(&self) -> Vec<(i32, i32)> {
+        (self.rows.0..=self.rows.1)
+            .cartesian_product(self.horizontal.0..=self.horizontal.1)
+            .collect()
+    }
+
+    fn neighbourhood(&self, y: i32, x: i32) -> impl Iterator<Item = (i32, i32)> + use<> {
+        let rows = (y - PYRAMID_REFINE_RADIUS).max(self.rows.0)
+            ..=(y + 1 + PYRAMID_REFINE_RADIUS).min(self.rows.1);
+        let horizontal = (x - PYRAMID_REFINE_RADIUS).max(self.horizontal.0)
+            ..=(x + 1 + PYRAMID_REFINE_RADIUS).min(self.horizontal.1);
+
+        rows.cartesian_product(horizontal)
+    }
+
+    /// Moves the level onto the halved planes of both images, returns `None` once no offsets
+    /// are left on them.
+    fn downscale(&self, first: &'a MatchPlane, second: &'a MatchPlane) -> Option<Self> {
+        let second_start = self.second_start / 2;
+        let window_size = self
+            .window_size
+            .div_ceil(2)
+            .min(second.values.height() - second_start);
+
+        let level = Self {
+            rows: (
+                (self.rows.0 >> 1).max(Self::lowest_row(&second.values, second_start, window_size)),
+                (self.rows.1 >> 1).min(first.values.height() as i32 - window_size as i32),
+            ),
+            horizontal: (self.horizontal.0 >> 1, self.horizontal.1 >> 1),
+            second_start,
+            window_size,
+            first: &first.values,
+            second: &second.values,
+            first_mask: first.mask.as_ref(),
+            second_mask: second.mask.as_ref(),
+            metric: self.metric,
+            deterministic: self.deterministic,
+        };
+
+        match level.is_empty() {
+            true => None,
+            false => Some(level),
+        }
     }
 }
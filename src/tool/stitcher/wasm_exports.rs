@@ -7,10 +7,14 @@ use image::{
 };
 use js_sys::{Array, BigInt, Uint8Array};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::{collections::VecDeque, io::Cursor, str::FromStr};
+use std::{io::Cursor, str::FromStr, time::Duration};
 use wasm_bindgen::{JsValue, prelude::wasm_bindgen};
 
-use crate::tool::stitcher::{CheckDirection, ImageStitcherBuilder, MatchMode, Order, Position};
+use crate::tool::stitcher::{
+    Align, BlendMode, CancelToken, CheckDirection, Color, Exclusions, ImageStitcherBuilder,
+    LowConfidence, MatchMode, Observer, OffsetHints, Order, ScaleMode, SeamMode, SearchMode,
+    StickyMode, StitchEvent, StitchObserver, StitchReport,
+};
 use image::Pixel;
 
 #[derive(Debug)]
@@ -59,7 +63,7 @@ impl Preview {
 #[wasm_bindgen]
 pub struct StitchedImage {
     image: Vec<u8>,
-    stitch_positions: VecDeque<Position>,
+    report: StitchReport,
     pub width: u32,
     pub height: u32,
 }
@@ -68,7 +72,7 @@ pub struct StitchedImage {
 impl StitchedImage {
     #[wasm_bindgen(
         js_name = "toJson",
-        unchecked_return_type = "{ image: Uint8Array, stitchPositions: Array<{ x: number, y: number }>, width: number, height: number }"
+        unchecked_return_type = "{ image: Uint8Array, stitchPositions: Array<{ x: number, y: number }>, seams: Array<{ first: number, second: number, score: number, flipped: boolean, meanDifference: number, ambiguity: number, confidence: number, flagged: boolean, truncated: boolean, direction: string }>, stickyBands: { header: number, footer: number }, inputs: Array<{ sequence: number, x: number, y: number, width: number, height: number, scale: number }>, direction: string, crop: number, timings: { matchingMs: number, compositingMs: number }, width: number, height: number }"
     )]
     pub fn to_json(self) -> js_sys::Object {
         let obj = js_sys::Object::new();
@@ -83,12 +87,59 @@ impl StitchedImage {
             &obj,
             &JsValue::from_str("stitchPositions"),
             &self
-                .stitch_positions
+                .report
+                .positions
                 .into_iter()
                 .map(|p| p.to_json())
                 .collect::<Array>(),
         )
         .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &JsValue::from_str("seams"),
+            &self
+                .report
+                .seams
+                .into_iter()
+                .map(|s| s.to_json())
+                .collect::<Array>(),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &JsValue::from_str("stickyBands"),
+            &self.report.sticky_bands.to_json(),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &JsValue::from_str("inputs"),
+            &self
+                .report
+                .inputs
+                .into_iter()
+                .map(|i| i.to_json())
+                .collect::<Array>(),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &JsValue::from_str("direction"),
+            &JsValue::from_str(&format!("{:?}", self.report.direction)),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &JsValue::from_str("crop"),
+            &JsValue::from(self.report.crop),
+        )
+        .ok();
+        js_sys::Reflect::set(
+            &obj,
+            &JsValue::from_str("timings"),
+            &self.report.timings.to_json(),
+        )
+        .ok();
         js_sys::Reflect::set(
             &obj,
             &JsValue::from_str("width"),
@@ -107,17 +158,12 @@ impl StitchedImage {
 }
 
 impl StitchedImage {
-    pub fn new(
-        image: Vec<u8>,
-        width: u32,
-        height: u32,
-        stitch_positions: VecDeque<Position>,
-    ) -> Self {
+    pub fn new(image: Vec<u8>, width: u32, height: u32, report: StitchReport) -> Self {
         Self {
             width,
             height,
             image,
-            stitch_positions,
+            report,
         }
     }
 }
@@ -143,6 +189,36 @@ impl StitchReturn {
     }
 }
 
+/// Forwards the events of a stitch to a JS function.
+struct JsObserver(js_sys::Function);
+
+// The module is built without threads, so the function is only ever called from the thread
+// that passed it in.
+unsafe impl Send for JsObserver {}
+unsafe impl Sync for JsObserver {}
+
+impl StitchObserver for JsObserver {
+    fn on_event(&self, event: StitchEvent) {
+        self.0.call1(&JsValue::NULL, &event.to_json()).ok();
+    }
+}
+
+/// Asks a JS function whether the stitch was cancelled, it can read a flag the page shares with
+/// the worker the stitch runs in.
+struct JsCancel(js_sys::Function);
+
+// Same as for `JsObserver`, there are no other threads to call it from.
+unsafe impl Send for JsCancel {}
+unsafe impl Sync for JsCancel {}
+
+impl JsCancel {
+    fn is_cancelled(&self) -> bool {
+        self.0
+            .call0(&JsValue::NULL)
+            .is_ok_and(|cancelled| cancelled.is_truthy())
+    }
+}
+
 #[wasm_bindgen]
 pub fn stitch(
     images: Vec<Uint8Array>,
@@ -153,6 +229,30 @@ pub fn stitch(
     crop_padding: Option<u32>,
     preview: Option<Preview>,
     format: EncodeFormat,
+    search_mode: Option<String>,
+    blend: Option<String>,
+    blend_width: Option<u32>,
+    seam: Option<String>,
+    min_confidence: Option<f64>,
+    low_confidence: Option<String>,
+    alpha_threshold: Option<u8>,
+    exclusions: Option<String>,
+    sticky: Option<String>,
+    offset_hints: Option<String>,
+    grid_columns: Option<usize>,
+    scale: Option<String>,
+    align: Option<String>,
+    background: Option<String>,
+    padding: Option<u32>,
+    gap: Option<u32>,
+    separator: Option<String>,
+    #[wasm_bindgen(
+        unchecked_param_type = "(event: { type: 'pairStarted' | 'pairProgress' | 'pairFinished' | 'compositingStarted' | 'compositingFinished', first?: number, second?: number, direction?: string, progress?: number, meanDifference?: number, ambiguity?: number, confidence?: number, flagged?: boolean, truncated?: boolean }) => void"
+    )]
+    observer: Option<js_sys::Function>,
+    time_budget_ms: Option<f64>,
+    #[wasm_bindgen(unchecked_param_type = "() => boolean")] is_cancelled: Option<js_sys::Function>,
+    deterministic: Option<bool>,
 ) -> Result<StitchReturn, String> {
     let direction = CheckDirection::from_str(&direction).map_err(|e| format!("{:#?}", e))?;
     let order = Order::from_str(&order).map_err(|e| format!("{:#?}", e))?;
@@ -161,6 +261,46 @@ pub fn stitch(
     let match_mode = match_mode
         .map(|s| MatchMode::from_str(&s).map_err(|e| format!("{:#?}", e)))
         .unwrap_or(Ok(MatchMode::Edges))?;
+    let search_mode = search_mode
+        .map(|s| SearchMode::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(SearchMode::Pyramid))?;
+    let blend = blend
+        .map(|s| BlendMode::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(BlendMode::HardCut))?;
+    let blend = match blend_width {
+        Some(width) => blend.with_width(width),
+        None => blend,
+    };
+    let seam = seam
+        .map(|s| SeamMode::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(SeamMode::Straight))?;
+    let exclusions = exclusions
+        .map(|s| Exclusions::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .transpose()?;
+    let sticky = sticky
+        .map(|s| StickyMode::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(StickyMode::Off))?;
+    let offset_hints = offset_hints
+        .map(|s| OffsetHints::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .transpose()?;
+    let scale = scale
+        .map(|s| ScaleMode::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(ScaleMode::Off))?;
+    let align = align
+        .map(|s| Align::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(Align::Content))?;
+    let background = background
+        .map(|s| Color::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .transpose()?;
+    let separator = separator
+        .map(|s| Color::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .transpose()?;
+    let low_confidence = low_confidence
+        .map(|s| LowConfidence::from_str(&s).map_err(|e| format!("{:#?}", e)))
+        .unwrap_or(Ok(LowConfidence::Fail))?;
+    let time_budget = time_budget_ms
+        .map(|ms| Duration::try_from_secs_f64(ms / 1000.0).map_err(|e| format!("{:#?}", e)))
+        .transpose()?;
 
     let images: Vec<_> = images.into_iter().map(|u| u.to_vec()).collect();
 
@@ -190,10 +330,33 @@ pub fn stitch(
         .window_size(window_size)
         .match_mode(match_mode)
         .crop(crop_padding)
+        .search_mode(search_mode)
+        .blend(blend)
+        .seam(seam)
+        .align(align)
+        .background(background)
+        .padding(padding)
+        .gap(gap)
+        .separator(separator)
+        .min_confidence(min_confidence)
+        .low_confidence(low_confidence)
+        .alpha_threshold(alpha_threshold)
+        .exclusions(exclusions)
+        .sticky(sticky)
+        .offset_hints(offset_hints)
+        .grid_columns(grid_columns)
+        .scale(scale)
+        .observer(observer.map(|observer| Observer::new(JsObserver(observer))))
+        .time_budget(time_budget)
+        .deterministic(deterministic)
+        .cancel(is_cancelled.map(|is_cancelled| {
+            let is_cancelled = JsCancel(is_cancelled);
+            CancelToken::polling(move || is_cancelled.is_cancelled())
+        }))
         .build()
         .map_err(|err| format!("{:#?}", err))?;
 
-    let (final_image, stitch_positions) = stitcher.stitch();
+    let (final_image, report) = stitcher.stitch().map_err(|err| format!("{:#?}", err))?;
 
     let stitched_image_data = encode_image_as(&final_image, ImageFormat::Png)
         .map_err(|e| format!("Failed to encode image: {:#?}", e))?;
@@ -202,7 +365,7 @@ pub fn stitch(
         stitched_image_data,
         final_image.width(),
         final_image.height(),
-        stitch_positions,
+        report,
     );
 
     let preview_image = preview
@@ -226,7 +389,8 @@ pub fn stitch(
             )
             .ok();
 
-            resized_image.map(|data| StitchedImage::new(data, width, height, Default::default()))
+            resized_image
+                .map(|data| StitchedImage::new(data, width, height, StitchReport::default()))
         })
         .flatten();
 
@@ -2,7 +2,7 @@ use std::borrow::Cow;
 
 use image::{ImageBuffer, Pixel, imageops::crop_imm};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ImageCrop {
     pub top: u32,
     pub bottom: u32,
@@ -16,10 +16,24 @@ impl ImageCrop {
             top: self.bottom,
             bottom: self.top,
             left: self.right,
-            right: self.top,
+            right: self.left,
         }
     }
 
+    /// The `(x, y, width, height)` area of a `width * height` image that [`ImageCrop::crop_image`]
+    /// keeps.
+    pub fn region(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = self.left.min(width);
+        let y = self.top.min(height);
+
+        (
+            x,
+            y,
+            (width - x).saturating_sub(self.right),
+            (height - y).saturating_sub(self.bottom),
+        )
+    }
+
     pub fn crop_image<'a, P>(
         &self,
         image: &'a ImageBuffer<P, Vec<P::Subpixel>>,
@@ -31,12 +45,7 @@ impl ImageCrop {
             return Cow::Borrowed(image);
         }
 
-        let (x, y, width, height) = (
-            self.left,
-            self.top,
-            image.width() - self.right,
-            image.height() - self.bottom,
-        );
+        let (x, y, width, height) = self.region(image.width(), image.height());
 
         Cow::Owned(crop_imm(image, x, y, width, height).to_image())
     }
@@ -2,3 +2,4 @@ pub mod dhash;
 pub mod image;
 pub mod iter;
 pub mod string;
+pub mod time;
@@ -0,0 +1,443 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+};
+
+use image::{Rgba, RgbaImage, imageops::crop_imm};
+use wonfy_tools::{
+    error::StitchError,
+    tool::stitcher::{
+        BlendMode, CheckDirection, Color, CompositeOptions, Exclusions, ImageStitcher,
+        ImageStitcherBuilder, MatchMode, Observer, OffsetHints, Order, Position, Rect, SeamMode,
+        SearchMode, StickyMode, Threads,
+    },
+};
+
+/// A page of noisy strips, so every offset along it looks different.
+fn page(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let block = (x / 4).wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663);
+        let value = block.wrapping_mul(2_654_435_761) >> 24;
+
+        Rgba([value as u8, (value * 3) as u8, 255 - value as u8, 255])
+    })
+}
+
+/// Frames of `page` starting at `offsets` along the page, the way a scrolling capture would.
+fn frames(page: &RgbaImage, offsets: &[u32], length: u32) -> Vec<RgbaImage> {
+    offsets
+        .iter()
+        .map(|&offset| crop_imm(page, 0, offset, page.width(), length).to_image())
+        .collect()
+}
+
+fn search(
+    images: Vec<RgbaImage>,
+    (match_mode, window_size): (MatchMode, usize),
+    search_mode: SearchMode,
+    threads: Threads,
+) -> Vec<Position> {
+    let (_, report) = ImageStitcherBuilder::new()
+        .images(images)
+        .direction(CheckDirection::Vertical)
+        .order(Order::Ordered)
+        .window_size(window_size)
+        .match_mode(match_mode)
+        .search_mode(search_mode)
+        .threads(threads)
+        .deterministic(true)
+        .build()
+        .unwrap()
+        .stitch()
+        .unwrap();
+
+    report.positions.into_iter().collect()
+}
+
+#[test]
+fn deterministic_ties_overlap_the_least() {
+    let images = vec![RgbaImage::from_pixel(50, 200, Rgba([255, 255, 255, 255])); 3];
+    let expected = vec![Position { x: 0, y: 388 }, Position { x: 0, y: 194 }];
+
+    for search_mode in [SearchMode::Exhaustive, SearchMode::Pyramid] {
+        for threads in [Threads::Global, Threads::Single] {
+            assert_eq!(
+                search(images.clone(), (MatchMode::Normal, 6), search_mode, threads),
+                expected,
+                "{:?}",
+                search_mode
+            );
+        }
+    }
+}
+
+#[test]
+fn pyramid_and_exhaustive_agree() {
+    let page = page(120, 700);
+    let images = frames(&page, &[0, 90, 230, 300, 480], 200);
+    let expected = vec![
+        Position { x: 0, y: 480 },
+        Position { x: 0, y: 300 },
+        Position { x: 0, y: 230 },
+        Position { x: 0, y: 90 },
+    ];
+
+    // Edges are normalised per image, a single row of them is too noisy to match on.
+    let settings = [
+        (MatchMode::Normal, 1),
+        (MatchMode::Normal, 6),
+        (MatchMode::Edges, 6),
+        (MatchMode::Ncc, 1),
+        (MatchMode::Ncc, 6),
+    ];
+
+    for settings in settings {
+        let exhaustive = search(
+            images.clone(),
+            settings.clone(),
+            SearchMode::Exhaustive,
+            Threads::Global,
+        );
+        let pyramid = search(
+            images.clone(),
+            settings.clone(),
+            SearchMode::Pyramid,
+            Threads::Global,
+        );
+
+        assert_eq!(exhaustive, expected, "{:?}", settings);
+        assert_eq!(pyramid, exhaustive, "{:?}", settings);
+    }
+}
+
+#[test]
+fn windows_that_cannot_match_are_rejected() {
+    let images = frames(&page(120, 400), &[0, 100], 200);
+    let stitch = |window_size, crop| {
+        ImageStitcherBuilder::new()
+            .images(images.clone())
+            .direction(CheckDirection::Vertical)
+            .order(Order::Ordered)
+            .window_size(window_size)
+            .match_mode(MatchMode::Normal)
+            .crop(crop)
+            .build()
+            .unwrap()
+            .stitch()
+            .map(|(image, _)| image)
+    };
+
+    assert_eq!(stitch(0, 0), Err(StitchError::EmptyWindow));
+    assert_eq!(
+        stitch(190, 20),
+        Err(StitchError::WindowLargerThanImage {
+            index: 0,
+            window_size: 190,
+            size: 180
+        })
+    );
+}
+
+#[test]
+fn phase_correlation_finds_small_overlaps() {
+    let page = page(120, 740);
+    let images = frames(&page, &[0, 170, 350, 540], 200);
+    let expected = vec![
+        Position { x: 0, y: 540 },
+        Position { x: 0, y: 350 },
+        Position { x: 0, y: 170 },
+    ];
+
+    for window_size in [1, 6] {
+        assert_eq!(
+            search(
+                images.clone(),
+                (MatchMode::PhaseCorrelation, window_size),
+                SearchMode::Pyramid,
+                Threads::Global
+            ),
+            expected,
+            "{}",
+            window_size
+        );
+    }
+}
+
+#[test]
+fn phase_correlation_follows_hints() {
+    let images = frames(&page(120, 400), &[0, 90], 200);
+
+    let (_, report) = ImageStitcherBuilder::new()
+        .images(images)
+        .direction(CheckDirection::Vertical)
+        .order(Order::Ordered)
+        .window_size(6)
+        .match_mode(MatchMode::PhaseCorrelation)
+        .offset_hints("0,90~3".parse::<OffsetHints>().unwrap())
+        .build()
+        .unwrap()
+        .stitch()
+        .unwrap();
+
+    assert_eq!(report.positions, [Position { x: 0, y: 90 }]);
+}
+
+#[test]
+fn ncc_is_confident_across_brightness_changes() {
+    let mut images = frames(&page(120, 400), &[0, 90], 200);
+
+    for pixel in images[1].pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = (pixel[channel] as f32 * 0.6 + 10.0) as u8;
+        }
+    }
+
+    let (_, report) = ImageStitcherBuilder::new()
+        .images(images)
+        .direction(CheckDirection::Vertical)
+        .order(Order::Ordered)
+        .window_size(6)
+        .match_mode(MatchMode::Ncc)
+        .min_confidence(0.9)
+        .build()
+        .unwrap()
+        .stitch()
+        .unwrap();
+
+    assert_eq!(report.positions, [Position { x: 0, y: 90 }]);
+}
+
+#[test]
+fn exclusions_over_the_window_are_skipped() {
+    let mut images = frames(&page(120, 400), &[0, 90, 200], 200);
+
+    // A banner drawn over the top of every frame, the same on each one.
+    for image in images.iter_mut() {
+        for y in 0..60 {
+            for x in 0..120 {
+                image.put_pixel(x, y, Rgba([200, 40, 40, 255]));
+            }
+        }
+    }
+
+    for match_mode in [
+        MatchMode::Normal,
+        MatchMode::Ncc,
+        MatchMode::PhaseCorrelation,
+    ] {
+        let (_, report) = ImageStitcherBuilder::new()
+            .images(images.clone())
+            .direction(CheckDirection::Vertical)
+            .order(Order::Ordered)
+            .window_size(6)
+            .match_mode(match_mode.clone())
+            .exclusions(Exclusions::Shared(vec![Rect::new(0, 0, 120, 60)]))
+            .build()
+            .unwrap()
+            .stitch()
+            .unwrap();
+
+        assert_eq!(
+            report.positions,
+            [Position { x: 0, y: 200 }, Position { x: 0, y: 90 }],
+            "{:?}",
+            match_mode
+        );
+    }
+
+    let hidden = ImageStitcherBuilder::new()
+        .images(images)
+        .direction(CheckDirection::Vertical)
+        .order(Order::Ordered)
+        .window_size(6)
+        .match_mode(MatchMode::Normal)
+        .exclusions(Exclusions::PerImage(vec![
+            vec![],
+            vec![Rect::new(0, 0, 120, 200)],
+            vec![],
+        ]))
+        .build()
+        .unwrap()
+        .stitch()
+        .map(|(image, _)| image);
+
+    assert_eq!(
+        hidden,
+        Err(StitchError::NothingToMatch {
+            first: 0,
+            second: 1
+        })
+    );
+}
+
+#[test]
+fn unordered_sticky_bands_come_from_the_ends_of_the_sequence() {
+    let frames = frames(&page(120, 400), &[0, 90, 200], 200);
+
+    // Bands that only differ by a shade between the frames, so they still count as sticky.
+    let framed = |index: usize| {
+        let shade = 10 + index as u8;
+
+        RgbaImage::from_fn(120, 250, |x, y| match y {
+            ..30 => Rgba([shade, 60, 90, 255]),
+            230.. => Rgba([90, 60, shade, 255]),
+            _ => *frames[index].get_pixel(x, y - 30),
+        })
+    };
+    let images = vec![framed(2), framed(0), framed(1)];
+
+    let (image, report) = ImageStitcherBuilder::new()
+        .images(images)
+        .direction(CheckDirection::Vertical)
+        .order(Order::Unordered)
+        .window_size(6)
+        .match_mode(MatchMode::Normal)
+        .sticky(StickyMode::Auto)
+        .build()
+        .unwrap()
+        .stitch()
+        .unwrap();
+
+    assert_eq!(*image.get_pixel(0, 0), Rgba([10, 60, 90, 255]));
+    assert_eq!(
+        *image.get_pixel(0, image.height() - 1),
+        Rgba([90, 60, 12, 255])
+    );
+
+    let bands = &report.placements[report.placements.len() - 2..];
+    assert_eq!((bands[0].index, bands[1].index), (1, 0));
+}
+
+#[test]
+fn sessions_stitch_the_same_as_a_batch() {
+    let images = frames(&page(120, 700), &[0, 90, 230, 300, 480], 200);
+    let builder = || {
+        ImageStitcherBuilder::new()
+            .direction(CheckDirection::Vertical)
+            .window_size(6)
+            .match_mode(MatchMode::Normal)
+            .crop(3)
+    };
+
+    let (batch_image, report) = builder()
+        .images(images.clone())
+        .order(Order::Ordered)
+        .build()
+        .unwrap()
+        .stitch()
+        .unwrap();
+
+    let mut session = builder().build_session().unwrap();
+
+    for image in images {
+        session.push(image).unwrap();
+    }
+
+    let (session_image, positions, seams) = session.finish().unwrap();
+
+    assert_eq!(positions, report.positions);
+    assert_eq!(
+        seams,
+        report
+            .seams
+            .into_iter()
+            .map(|seam| seam.confidence)
+            .collect::<Vec<_>>()
+    );
+    assert!(session_image == batch_image);
+}
+
+#[test]
+fn compositing_the_placements_gives_back_the_stitch() {
+    let frames = frames(&page(120, 700), &[0, 90, 230, 300, 480], 200);
+    let images = [3, 0, 4, 1, 2].map(|index| frames[index].clone()).to_vec();
+
+    for (blend, seam) in [
+        (BlendMode::HardCut, SeamMode::Straight),
+        (BlendMode::Feather(16), SeamMode::MinCost),
+        (BlendMode::MultiBand(16), SeamMode::Straight),
+    ] {
+        let (image, report) = ImageStitcherBuilder::new()
+            .images(images.clone())
+            .direction(CheckDirection::Vertical)
+            .order(Order::Unordered)
+            .window_size(6)
+            .match_mode(MatchMode::Normal)
+            .crop(3)
+            .blend(blend)
+            .seam(seam)
+            .background(Color(Rgba([20, 20, 20, 255])))
+            .padding(4)
+            .build()
+            .unwrap()
+            .stitch()
+            .unwrap();
+
+        let options = CompositeOptions {
+            blend,
+            seam,
+            background: Some(Color(Rgba([20, 20, 20, 255]))),
+            padding: 4,
+            ..Default::default()
+        };
+        let composited = ImageStitcher::fill_canvas(
+            ImageStitcher::composite(&images, &report.placements, &options).unwrap(),
+            &options,
+        );
+
+        assert!(composited == image, "{:?} {:?}", blend, seam);
+    }
+}
+
+#[test]
+fn hints_without_an_overlap_are_reported() {
+    let images = frames(&page(120, 400), &[0, 90, 200], 200);
+
+    let stitched = ImageStitcherBuilder::new()
+        .images(images)
+        .direction(CheckDirection::Vertical)
+        .order(Order::Ordered)
+        .window_size(6)
+        .match_mode(MatchMode::Normal)
+        .offset_hints("0,90~3;0,5000~0".parse::<OffsetHints>().unwrap())
+        .build()
+        .unwrap()
+        .stitch()
+        .map(|(image, _)| image);
+
+    assert_eq!(
+        stitched,
+        Err(StitchError::HintOutOfRange {
+            first: 1,
+            second: 2
+        })
+    );
+}
+
+#[test]
+fn sessions_keep_their_single_thread() {
+    let images = frames(&page(120, 400), &[0, 90, 200], 200);
+    let threads = Arc::new(Mutex::new(Vec::<ThreadId>::new()));
+    let seen = threads.clone();
+
+    let mut session = ImageStitcherBuilder::new()
+        .direction(CheckDirection::Vertical)
+        .window_size(6)
+        .match_mode(MatchMode::Normal)
+        .threads(Threads::Single)
+        .observer(Observer::new(move |_| {
+            seen.lock().unwrap().push(thread::current().id())
+        }))
+        .build_session()
+        .unwrap();
+
+    for image in images {
+        session.push(image).unwrap();
+    }
+
+    let threads = threads.lock().unwrap();
+
+    assert!(!threads.is_empty());
+    assert!(threads.iter().all(|&id| id == threads[0]));
+    assert_ne!(threads[0], thread::current().id());
+}